@@ -0,0 +1,319 @@
+use egui_macroquad::macroquad::prelude::*;
+
+/// where bindings are saved on native builds
+#[cfg(not(target_arch = "wasm32"))]
+const CONFIG_FILE: &str = "keybindings.txt";
+
+/// Something the player can do, independent of which keys do it
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Action {
+    ThrottleUp,
+    ThrottleDown,
+    ThrottleMax,
+    ThrottleCut,
+    RotateCCW,
+    RotateCW,
+    Stage,
+    ToggleSas,
+    ToggleMap,
+    ToggleMapRings,
+    MapZoomIn,
+    MapZoomOut,
+    ZoomIn,
+    ZoomOut,
+    CycleCamera,
+    Pause,
+    StepTick,
+    StepTicks,
+    WarpFaster,
+    WarpSlower,
+}
+
+impl Action {
+    pub const ALL: [Action; 20] = [
+        Action::ThrottleUp,
+        Action::ThrottleDown,
+        Action::ThrottleMax,
+        Action::ThrottleCut,
+        Action::RotateCCW,
+        Action::RotateCW,
+        Action::Stage,
+        Action::ToggleSas,
+        Action::ToggleMap,
+        Action::ToggleMapRings,
+        Action::MapZoomIn,
+        Action::MapZoomOut,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::CycleCamera,
+        Action::Pause,
+        Action::StepTick,
+        Action::StepTicks,
+        Action::WarpFaster,
+        Action::WarpSlower,
+    ];
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::ThrottleUp => "Increase throttle",
+            Action::ThrottleDown => "Decrease throttle",
+            Action::ThrottleMax => "Full throttle",
+            Action::ThrottleCut => "Cut throttle",
+            Action::RotateCCW => "Rotate CCW",
+            Action::RotateCW => "Rotate CW",
+            Action::Stage => "Stage",
+            Action::ToggleSas => "Toggle SAS",
+            Action::ToggleMap => "Toggle map",
+            Action::ToggleMapRings => "Toggle map rings",
+            Action::MapZoomIn => "Zoom map in",
+            Action::MapZoomOut => "Zoom map out",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::CycleCamera => "Cycle camera",
+            Action::Pause => "Pause",
+            Action::StepTick => "Step one tick",
+            Action::StepTicks => "Step several ticks",
+            Action::WarpFaster => "Warp faster",
+            Action::WarpSlower => "Warp slower",
+        }
+    }
+
+    fn default_keys(&self) -> Vec<KeyCode> {
+        match self {
+            Action::ThrottleUp => vec![KeyCode::C, KeyCode::Space],
+            Action::ThrottleDown => vec![KeyCode::Z],
+            Action::ThrottleMax => vec![KeyCode::E],
+            Action::ThrottleCut => vec![KeyCode::Q],
+            Action::RotateCCW => vec![KeyCode::A],
+            Action::RotateCW => vec![KeyCode::D],
+            Action::Stage => vec![KeyCode::S],
+            Action::ToggleSas => vec![KeyCode::T],
+            Action::ToggleMap => vec![KeyCode::M],
+            Action::ToggleMapRings => vec![KeyCode::G],
+            Action::MapZoomIn => vec![KeyCode::Up],
+            Action::MapZoomOut => vec![KeyCode::Down],
+            Action::ZoomIn => vec![KeyCode::Equal],
+            Action::ZoomOut => vec![KeyCode::Minus],
+            Action::CycleCamera => vec![KeyCode::V],
+            Action::Pause => vec![KeyCode::P],
+            Action::StepTick => vec![KeyCode::Period],
+            Action::StepTicks => vec![KeyCode::Slash],
+            Action::WarpFaster => vec![KeyCode::RightBracket],
+            Action::WarpSlower => vec![KeyCode::LeftBracket],
+        }
+    }
+}
+
+/// Keys that can be bound, with the names they're saved under
+const KEY_NAMES: &[(KeyCode, &str)] = &[
+    (KeyCode::A, "A"),
+    (KeyCode::B, "B"),
+    (KeyCode::C, "C"),
+    (KeyCode::D, "D"),
+    (KeyCode::E, "E"),
+    (KeyCode::F, "F"),
+    (KeyCode::G, "G"),
+    (KeyCode::H, "H"),
+    (KeyCode::I, "I"),
+    (KeyCode::J, "J"),
+    (KeyCode::K, "K"),
+    (KeyCode::L, "L"),
+    (KeyCode::M, "M"),
+    (KeyCode::N, "N"),
+    (KeyCode::O, "O"),
+    (KeyCode::P, "P"),
+    (KeyCode::Q, "Q"),
+    (KeyCode::R, "R"),
+    (KeyCode::S, "S"),
+    (KeyCode::T, "T"),
+    (KeyCode::U, "U"),
+    (KeyCode::V, "V"),
+    (KeyCode::W, "W"),
+    (KeyCode::X, "X"),
+    (KeyCode::Y, "Y"),
+    (KeyCode::Z, "Z"),
+    (KeyCode::Key0, "0"),
+    (KeyCode::Key1, "1"),
+    (KeyCode::Key2, "2"),
+    (KeyCode::Key3, "3"),
+    (KeyCode::Key4, "4"),
+    (KeyCode::Key5, "5"),
+    (KeyCode::Key6, "6"),
+    (KeyCode::Key7, "7"),
+    (KeyCode::Key8, "8"),
+    (KeyCode::Key9, "9"),
+    (KeyCode::Space, "Space"),
+    (KeyCode::Enter, "Enter"),
+    (KeyCode::Tab, "Tab"),
+    (KeyCode::Backspace, "Backspace"),
+    (KeyCode::Minus, "-"),
+    (KeyCode::Equal, "="),
+    (KeyCode::LeftBracket, "["),
+    (KeyCode::RightBracket, "]"),
+    (KeyCode::Comma, ","),
+    (KeyCode::Period, "."),
+    (KeyCode::Slash, "/"),
+    (KeyCode::Up, "Up"),
+    (KeyCode::Down, "Down"),
+    (KeyCode::Left, "Left"),
+    (KeyCode::Right, "Right"),
+    (KeyCode::LeftShift, "LeftShift"),
+    (KeyCode::LeftControl, "LeftControl"),
+    (KeyCode::F1, "F1"),
+    (KeyCode::F2, "F2"),
+    (KeyCode::F3, "F3"),
+    (KeyCode::F4, "F4"),
+    (KeyCode::F5, "F5"),
+    (KeyCode::F6, "F6"),
+    (KeyCode::F7, "F7"),
+    (KeyCode::F8, "F8"),
+    (KeyCode::F9, "F9"),
+    (KeyCode::F10, "F10"),
+    (KeyCode::F11, "F11"),
+    (KeyCode::F12, "F12"),
+];
+
+pub fn key_name(key: KeyCode) -> Option<&'static str> {
+    KEY_NAMES
+        .iter()
+        .find(|(code, _)| *code == key)
+        .map(|(_, name)| *name)
+}
+
+fn key_from_name(name: &str) -> Option<KeyCode> {
+    KEY_NAMES
+        .iter()
+        .find(|(_, key_name)| *key_name == name)
+        .map(|(code, _)| *code)
+}
+
+/// Which keys trigger each action, consumed by gameplay systems in place of raw keys
+#[derive(Clone)]
+pub struct ActionMap {
+    bindings: Vec<(Action, Vec<KeyCode>)>,
+}
+
+impl Default for ActionMap {
+    fn default() -> Self {
+        ActionMap {
+            bindings: Action::ALL
+                .iter()
+                .map(|action| (*action, action.default_keys()))
+                .collect(),
+        }
+    }
+}
+
+impl ActionMap {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        self.bindings
+            .iter()
+            .find(|(bound_action, _)| *bound_action == action)
+            .map_or(&[][..], |(_, keys)| keys.as_slice())
+    }
+
+    /// Replaces the keys for `action`, taking `key` off any other action it was bound to
+    pub fn bind(&mut self, action: Action, key: KeyCode) {
+        for (bound_action, keys) in self.bindings.iter_mut() {
+            if *bound_action == action {
+                *keys = vec![key];
+            } else {
+                keys.retain(|bound_key| *bound_key != key);
+            }
+        }
+    }
+
+    pub fn is_down(&self, action: Action) -> bool {
+        self.keys(action).iter().any(|key| is_key_down(*key))
+    }
+
+    pub fn is_pressed(&self, action: Action) -> bool {
+        self.keys(action).iter().any(|key| is_key_pressed(*key))
+    }
+
+    /// One line per action: its name followed by the names of its keys
+    pub fn to_config(&self) -> String {
+        self.bindings
+            .iter()
+            .map(|(action, keys)| {
+                let key_names = keys.iter().filter_map(|key| key_name(*key));
+                std::iter::once(format!("{:?}", action))
+                    .chain(key_names.map(String::from))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+                    + "\n"
+            })
+            .collect()
+    }
+
+    /// Actions missing from `config` keep their default keys, unknown names are ignored
+    pub fn from_config(config: &str) -> Self {
+        let mut action_map = ActionMap::default();
+
+        for line in config.lines() {
+            let mut words = line.split_whitespace();
+            let action = words.next().and_then(|name| {
+                Action::ALL
+                    .iter()
+                    .find(|action| format!("{:?}", action) == name)
+            });
+
+            if let Some(action) = action {
+                let keys = words.filter_map(key_from_name).collect();
+                for (bound_action, bound_keys) in action_map.bindings.iter_mut() {
+                    if *bound_action == *action {
+                        *bound_keys = keys;
+                        break;
+                    }
+                }
+            }
+        }
+
+        action_map
+    }
+
+    /// Loads the saved bindings, falling back to the defaults
+    pub fn load() -> Self {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Ok(config) = std::fs::read_to_string(CONFIG_FILE) {
+            return ActionMap::from_config(&config);
+        }
+
+        ActionMap::default()
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn save(&self) -> Result<(), crate::error::GameError> {
+        std::fs::write(CONFIG_FILE, self.to_config())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_round_trips() {
+        let mut action_map = ActionMap::default();
+        action_map.bind(Action::Stage, KeyCode::F5);
+        action_map.bind(Action::ToggleMap, KeyCode::Space);
+
+        let parsed = ActionMap::from_config(&action_map.to_config());
+        for action in Action::ALL.iter() {
+            assert_eq!(parsed.keys(*action), action_map.keys(*action));
+        }
+    }
+
+    #[test]
+    fn missing_and_unknown_lines_keep_defaults() {
+        let parsed = ActionMap::from_config("Stage F5\nNotAnAction A\n");
+        let defaults = ActionMap::default();
+
+        assert_eq!(parsed.keys(Action::Stage), &[KeyCode::F5][..]);
+        assert_eq!(
+            parsed.keys(Action::ToggleMap),
+            defaults.keys(Action::ToggleMap)
+        );
+    }
+}
@@ -1,14 +1,92 @@
+use crate::actions::{Action, ActionMap};
+use crate::map::MapRes;
+use crate::physics::offset::UniverseOffset;
 use crate::physics::Kinematics;
-use crate::rocket::RocketEntity;
+use crate::planet::CelestialBody;
+use crate::rocket::{Altitude, RocketEntity};
+use crate::ui::PointerOverUi;
 use bevy_ecs::prelude::*;
 use egui_macroquad::macroquad::prelude::*;
 
 pub struct CameraRes {
     pub camera: Camera2D,
     pub screen_size: Vec2,
+    /// how many times `SCREEN_HEIGHT` the view is, eased towards `target_zoom_level`
+    pub zoom_level: f32,
+    pub target_zoom_level: f32,
+    /// the window height that shows exactly `SCREEN_HEIGHT` at zoom level 1
+    base_screen_height: f32,
+    pub mode: CameraMode,
+    /// whether the chase camera shakes around the rocket
+    pub wobble: bool,
+    /// where the free camera is looking, including the universe offset so it
+    /// stays put when the universe is rebased
+    pub free_target: Vec2,
+    last_mouse_pos: Vec2,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum CameraMode {
+    /// follows the rocket
+    Chase,
+    /// dragged around with the mouse
+    FreePan,
+    /// follows the rocket, rotated so the nearest body is always down
+    Horizon,
+    /// follows a celestial body
+    Body(Entity),
+}
+
+impl CameraMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CameraMode::Chase => "Chase",
+            CameraMode::FreePan => "Free",
+            CameraMode::Horizon => "Horizon",
+            CameraMode::Body(_) => "Body",
+        }
+    }
+}
+
+pub const MIN_ZOOM_LEVEL: f32 = 0.1;
+pub const MAX_ZOOM_LEVEL: f32 = 20_000.0;
+
+/// how much one notch of the mouse wheel zooms
+const WHEEL_ZOOM_FACTOR: f32 = 1.25;
+/// how much holding a zoom key zooms per second
+const KEY_ZOOM_FACTOR: f32 = 3.0;
+/// how quickly the zoom level eases to its target, per second
+const ZOOM_SMOOTHING: f32 = 10.0;
+
+/// how far apart on screen, in pixels, the points of a curve are drawn
+const CURVE_SEGMENT_PIXELS: f32 = 6.0;
+const MIN_CURVE_SEGMENTS: usize = 8;
+const MAX_CURVE_SEGMENTS: usize = 2000;
+
+/// How many segments a curve `length` world units long needs to look smooth, when
+/// `view_radius` world units span the screen from its center to a corner
+pub fn curve_segments(length: f32, view_radius: f32) -> usize {
+    let screen_radius = Vec2::new(screen_width(), screen_height()).length() / 2.0;
+    let pixels = length / view_radius * screen_radius;
+    ((pixels / CURVE_SEGMENT_PIXELS).ceil() as usize).clamp(MIN_CURVE_SEGMENTS, MAX_CURVE_SEGMENTS)
 }
 
 impl CameraRes {
+    pub fn set_mode(&mut self, mode: CameraMode, universe_offset: Vec2) {
+        if mode == CameraMode::FreePan {
+            self.free_target = self.camera.target + universe_offset;
+        }
+        self.mode = mode;
+    }
+
+    /// size of the area the camera shows, in world units
+    pub fn view_size(&self) -> Vec2 {
+        Vec2::new(
+            2.0 / self.camera.zoom.x.abs(),
+            2.0 / self.camera.zoom.y.abs(),
+        )
+    }
+
     pub fn contains_point(&self, point: &Vec2) -> bool {
         let camera_left = self.camera.target.x - self.screen_size.x / 2.0;
         let camera_right = self.camera.target.x + self.screen_size.x / 2.0;
@@ -32,38 +110,158 @@ impl Default for CameraRes {
         CameraRes {
             camera: Camera2D::from_display_rect(display_rect),
             screen_size: Vec2::new(screen_width(), screen_height()),
+            zoom_level: 1.0,
+            target_zoom_level: 1.0,
+            base_screen_height: screen_height(),
+            mode: CameraMode::Chase,
+            wobble: true,
+            free_target: Vec2::new(0.0, 0.0),
+            last_mouse_pos: Vec2::new(0.0, 0.0),
         }
     }
 }
 
 pub fn update_camera_sys(mut camera_res: ResMut<CameraRes>) {
-    let screen_height_change = screen_height() / camera_res.screen_size.y;
-    let aspect_ratio = camera_res.screen_size.x / camera_res.screen_size.y;
+    let frame_time = get_frame_time();
+    let zoom_ratio = camera_res.target_zoom_level / camera_res.zoom_level;
+    camera_res.zoom_level *= zoom_ratio.powf((frame_time * ZOOM_SMOOTHING).min(1.0));
 
     camera_res.screen_size.x = screen_width();
     camera_res.screen_size.y = screen_height();
+    let aspect_ratio = camera_res.screen_size.x / camera_res.screen_size.y;
+
+    // a taller window shows more of the world rather than stretching it
+    let view_height = crate::SCREEN_HEIGHT * camera_res.zoom_level * camera_res.screen_size.y
+        / camera_res.base_screen_height;
 
-    camera_res.camera.zoom.y /= screen_height_change;
+    camera_res.camera.zoom.y = 2.0 / view_height;
     camera_res.camera.zoom.x = camera_res.camera.zoom.y / aspect_ratio;
 
     set_camera(&camera_res.camera);
 }
 
+pub fn camera_zoom_input_sys(
+    mut camera_res: ResMut<CameraRes>,
+    map_res: Res<MapRes>,
+    action_map: Res<ActionMap>,
+    pointer_over_ui: Res<PointerOverUi>,
+) {
+    // the map uses the same controls for its own scale
+    if map_res.shown {
+        return;
+    }
+
+    let mut target_zoom_level = camera_res.target_zoom_level;
+
+    // scrolling over the UI is meant for it, not the view
+    if !pointer_over_ui.0 {
+        let (_, wheel_y) = mouse_wheel();
+        if wheel_y > 0.0 {
+            target_zoom_level /= WHEEL_ZOOM_FACTOR;
+        } else if wheel_y < 0.0 {
+            target_zoom_level *= WHEEL_ZOOM_FACTOR;
+        }
+    }
+
+    let key_zoom = KEY_ZOOM_FACTOR.powf(get_frame_time());
+    if action_map.is_down(Action::ZoomIn) {
+        target_zoom_level /= key_zoom;
+    }
+    if action_map.is_down(Action::ZoomOut) {
+        target_zoom_level *= key_zoom;
+    }
+
+    camera_res.target_zoom_level = target_zoom_level.clamp(MIN_ZOOM_LEVEL, MAX_ZOOM_LEVEL);
+}
+
+pub fn camera_mode_input_sys(
+    mut camera_res: ResMut<CameraRes>,
+    body_query: Query<Entity, With<CelestialBody>>,
+    offset: Res<UniverseOffset>,
+    map_res: Res<MapRes>,
+    action_map: Res<ActionMap>,
+    pointer_over_ui: Res<PointerOverUi>,
+) {
+    if action_map.is_pressed(Action::CycleCamera) {
+        let bodies = body_query.iter().collect::<Vec<_>>();
+        let next_mode = match camera_res.mode {
+            CameraMode::Chase => CameraMode::FreePan,
+            CameraMode::FreePan => CameraMode::Horizon,
+            CameraMode::Horizon => match bodies.first() {
+                Some(body) => CameraMode::Body(*body),
+                None => CameraMode::Chase,
+            },
+            CameraMode::Body(current) => {
+                let current_index = bodies.iter().position(|body| *body == current);
+                match current_index.and_then(|i| bodies.get(i + 1)) {
+                    Some(body) => CameraMode::Body(*body),
+                    None => CameraMode::Chase,
+                }
+            }
+        };
+        camera_res.set_mode(next_mode, offset.offset);
+    }
+
+    let mouse_pos = Vec2::from(mouse_position());
+    // dragging a slider shouldn't pan the camera too
+    if camera_res.mode == CameraMode::FreePan
+        && !map_res.shown
+        && !pointer_over_ui.0
+        && is_mouse_button_down(MouseButton::Left)
+    {
+        let last_world_pos = camera_res.camera.screen_to_world(camera_res.last_mouse_pos);
+        let world_pos = camera_res.camera.screen_to_world(mouse_pos);
+        camera_res.free_target -= world_pos - last_world_pos;
+    }
+    camera_res.last_mouse_pos = mouse_pos;
+}
+
 pub fn camera_follow_sys(
     mut camera_res: ResMut<CameraRes>,
     rocket_entity: Res<RocketEntity>,
     kinematics: Query<&Kinematics>,
+    altitude_query: Query<&Altitude>,
+    offset: Res<UniverseOffset>,
+    map_res: Res<MapRes>,
 ) {
     let rocket_entity = rocket_entity.0;
     let rocket_kinematics = kinematics.get(rocket_entity).unwrap();
 
-    camera_res.camera.target = rocket_kinematics.pos;
+    camera_res.camera.rotation = 0.0;
+    camera_res.camera.target = match camera_res.mode {
+        CameraMode::Chase => rocket_kinematics.pos,
+        CameraMode::FreePan => camera_res.free_target - offset.offset,
+        CameraMode::Horizon => {
+            // the map is drawn over the flight view and needs to stay upright
+            let altitude = altitude_query.get(rocket_entity).unwrap();
+            match kinematics.get(altitude.closest_planet) {
+                Ok(planet_kinematics) if !map_res.shown => {
+                    // rotate the view so the planet's radial direction points up
+                    let up = rocket_kinematics.pos - planet_kinematics.pos;
+                    let up_angle = up.y.atan2(up.x);
+                    camera_res.camera.rotation =
+                        (std::f32::consts::FRAC_PI_2 - up_angle).to_degrees();
+                }
+                _ => {}
+            }
+            rocket_kinematics.pos
+        }
+        CameraMode::Body(body) => match kinematics.get(body) {
+            Ok(body_kinematics) => body_kinematics.pos,
+            Err(_) => rocket_kinematics.pos,
+        },
+    };
 
-    let t = get_time();
-    let x_offset =
-        (t.cos() + (t * 1.7).cos() + (t / 3.5).cos() + (t - 2.0).sin() + (t / 2.1).sin()) as f32;
-    let y_offset =
-        (t.sin() + (t * 1.5).sin() + (t / 3.1).sin() + (t + 1.0).cos() + (t / 1.5).cos()) as f32;
-    camera_res.camera.target.x += x_offset * camera_res.screen_size.x / 25000.0;
-    camera_res.camera.target.y += y_offset * camera_res.screen_size.y / 25000.0;
+    if camera_res.wobble && camera_res.mode == CameraMode::Chase {
+        let t = get_time();
+        let x_offset =
+            (t.cos() + (t * 1.7).cos() + (t / 3.5).cos() + (t - 2.0).sin() + (t / 2.1).sin())
+                as f32;
+        let y_offset =
+            (t.sin() + (t * 1.5).sin() + (t / 3.1).sin() + (t + 1.0).cos() + (t / 1.5).cos())
+                as f32;
+        let zoom_level = camera_res.zoom_level;
+        camera_res.camera.target.x += x_offset * camera_res.screen_size.x / 25000.0 * zoom_level;
+        camera_res.camera.target.y += y_offset * camera_res.screen_size.y / 25000.0 * zoom_level;
+    }
 }
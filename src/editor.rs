@@ -0,0 +1,399 @@
+use egui_macroquad::egui;
+use egui_macroquad::macroquad::prelude::*;
+
+use crate::error::GameError;
+use crate::planet::CelestialBody;
+use crate::replay::Replay;
+use crate::rocket::{Rocket, ROCKET_LENGTH};
+
+#[derive(PartialEq, Eq, Copy, Clone)]
+pub enum PartKind {
+    CommandPod,
+    FuelTank,
+    Engine,
+    Decoupler,
+    Fins,
+}
+
+#[derive(Clone)]
+pub struct Part {
+    pub name: &'static str,
+    pub kind: PartKind,
+    pub dry_mass: f32,
+    pub fuel_capacity: f32,
+    pub fuel_burn_rate: f32,
+    pub fuel_thrust_factor: f32,
+    /// side area the part adds on top of its body's, in the same units as `height`,
+    /// which moves the center of pressure towards it
+    pub fin_area: f32,
+    pub lift_coefficient: f32,
+    /// height of the part when drawn in the editor, in pixels
+    pub height: f32,
+}
+
+impl Part {
+    fn new(name: &'static str, kind: PartKind, dry_mass: f32, height: f32) -> Self {
+        Part {
+            name,
+            kind,
+            dry_mass,
+            fuel_capacity: 0.0,
+            fuel_burn_rate: 0.0,
+            fuel_thrust_factor: 0.0,
+            fin_area: 0.0,
+            lift_coefficient: 0.0,
+            height,
+        }
+    }
+
+    pub fn total_mass(&self) -> f32 {
+        self.dry_mass + self.fuel_capacity
+    }
+
+    fn color(&self) -> Color {
+        match self.kind {
+            PartKind::CommandPod => LIGHTGRAY,
+            PartKind::FuelTank => WHITE,
+            PartKind::Engine => DARKGRAY,
+            PartKind::Decoupler => ORANGE,
+            PartKind::Fins => RED,
+        }
+    }
+}
+
+pub fn part_catalog() -> Vec<Part> {
+    vec![
+        Part::new("Command Pod", PartKind::CommandPod, 40.0, 40.0),
+        Part {
+            fuel_capacity: 600.0,
+            ..Part::new("Small Tank", PartKind::FuelTank, 12.0, 40.0)
+        },
+        Part {
+            fuel_capacity: 2500.0,
+            ..Part::new("Large Tank", PartKind::FuelTank, 40.0, 100.0)
+        },
+        Part {
+            fuel_burn_rate: 2.0,
+            fuel_thrust_factor: 500.0,
+            ..Part::new("Lifter Engine", PartKind::Engine, 20.0, 40.0)
+        },
+        Part {
+            fuel_burn_rate: 0.5,
+            fuel_thrust_factor: 700.0,
+            ..Part::new("Vacuum Engine", PartKind::Engine, 10.0, 30.0)
+        },
+        Part::new("Decoupler", PartKind::Decoupler, 5.0, 10.0),
+        Part {
+            fin_area: 60.0,
+            lift_coefficient: 0.2,
+            ..Part::new("Fins", PartKind::Fins, 5.0, 20.0)
+        },
+    ]
+}
+
+/// How far the center of pressure of `parts`, stacked top to bottom, sits below their
+/// center of mass, in world units. Each part's side area is its height plus any fin
+/// area, so a stack with no fins is about neutral and one with a heavy top is stable.
+fn stability_margin(parts: &[&Part]) -> f32 {
+    let mut top = 0.0;
+    let (mut mass, mut mass_moment) = (0.0, 0.0);
+    let (mut area, mut area_moment) = (0.0, 0.0);
+    for part in parts {
+        let center = top + part.height / 2.0;
+        mass += part.total_mass();
+        mass_moment += part.total_mass() * center;
+        area += part.height + part.fin_area;
+        area_moment += (part.height + part.fin_area) * center;
+        top += part.height;
+    }
+
+    if mass <= 0.0 || area <= 0.0 {
+        return 0.0;
+    }
+
+    let center_of_mass = mass_moment / mass;
+    let center_of_pressure = area_moment / area;
+    (center_of_pressure - center_of_mass) / top * ROCKET_LENGTH
+}
+
+/// Splits a top-to-bottom part stack into stages in firing order.
+/// A decoupler is dropped along with the stage below it.
+pub fn design_stages(parts: &[Part]) -> Vec<Rocket> {
+    let mut sections: Vec<Vec<&Part>> = vec![vec![]];
+    for part in parts {
+        if part.kind == PartKind::Decoupler {
+            sections.push(vec![]);
+        }
+        sections.last_mut().unwrap().push(part);
+    }
+
+    let mut payload_mass = 0.0;
+    // every part still attached when the stage is active
+    let mut stack: Vec<&Part> = Vec::with_capacity(parts.len());
+    let mut lift_coefficient = Rocket::default().lift_coefficient;
+    let mut stages = Vec::with_capacity(sections.len());
+    for section in sections.iter().filter(|section| !section.is_empty()) {
+        let dry_mass: f32 = section.iter().map(|part| part.dry_mass).sum();
+        let fuel_capacity: f32 = section.iter().map(|part| part.fuel_capacity).sum();
+        let fuel_burn_rate: f32 = section.iter().map(|part| part.fuel_burn_rate).sum();
+        let fuel_thrust_factor = if fuel_burn_rate > 0.0 {
+            section
+                .iter()
+                .map(|part| part.fuel_burn_rate * part.fuel_thrust_factor)
+                .sum::<f32>()
+                / fuel_burn_rate
+        } else {
+            0.0
+        };
+        stack.extend(section.iter());
+        lift_coefficient += section.iter().map(|part| part.lift_coefficient).sum::<f32>();
+
+        stages.push(Rocket {
+            fuel_capacity,
+            current_fuel_mass: fuel_capacity,
+            non_fuel_mass: dry_mass + payload_mass,
+            fuel_burn_rate,
+            fuel_thrust_factor,
+            stability: stability_margin(&stack),
+            lift_coefficient,
+            ..Rocket::default()
+        });
+
+        payload_mass += dry_mass + fuel_capacity;
+    }
+
+    stages.reverse();
+    stages
+}
+
+pub struct EditorState {
+    pub parts: Vec<Part>,
+    catalog: Vec<Part>,
+    bodies: Vec<CelestialBody>,
+    selected_body: usize,
+    launch_requested: bool,
+    /// file to load a replay from
+    replay_path: String,
+    replay_requested: Option<Replay>,
+    /// why the last replay failed to load
+    replay_error: Option<String>,
+}
+
+impl Default for EditorState {
+    fn default() -> Self {
+        EditorState::new()
+    }
+}
+
+impl EditorState {
+    pub fn new() -> Self {
+        let catalog = part_catalog();
+        let parts = vec![catalog[0].clone(), catalog[2].clone(), catalog[3].clone()];
+
+        EditorState {
+            parts,
+            catalog,
+            bodies: crate::planet::default_bodies()
+                .into_iter()
+                .map(|(body, _)| body)
+                .collect(),
+            selected_body: 0,
+            launch_requested: false,
+            replay_path: String::new(),
+            replay_requested: None,
+            replay_error: None,
+        }
+    }
+
+    /// Returns the stages of the design if launch was clicked this frame
+    pub fn take_launch(&mut self) -> Option<Vec<Rocket>> {
+        if self.launch_requested {
+            self.launch_requested = false;
+            Some(design_stages(&self.parts))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the replay to play back if one was loaded this frame
+    pub fn take_replay(&mut self) -> Option<Replay> {
+        self.replay_requested.take()
+    }
+
+    pub fn update(&mut self) -> Result<(), GameError> {
+        set_default_camera();
+        Ok(())
+    }
+
+    pub fn draw(&mut self) -> Result<(), GameError> {
+        clear_background(Color::new(0.1, 0.1, 0.15, 1.0));
+
+        let width = 60.0;
+        let x = screen_width() * 0.7 - width / 2.0;
+        let mut y = 50.0;
+        for part in self.parts.iter() {
+            if part.kind == PartKind::CommandPod {
+                draw_triangle(
+                    Vec2::new(x + width / 2.0, y),
+                    Vec2::new(x, y + part.height),
+                    Vec2::new(x + width, y + part.height),
+                    part.color(),
+                );
+            } else {
+                draw_rectangle(x, y, width, part.height, part.color());
+                draw_rectangle_lines(x, y, width, part.height, 2.0, BLACK);
+            }
+            y += part.height;
+        }
+
+        Ok(())
+    }
+
+    pub fn draw_ui(&mut self) -> Result<(), GameError> {
+        egui_macroquad::ui(|egui_ctx| {
+            use egui::{FontDefinitions, TextStyle};
+            let mut fonts = FontDefinitions::default();
+            fonts.family_and_size.get_mut(&TextStyle::Body).unwrap().1 = 24.0;
+            egui_ctx.set_fonts(fonts);
+
+            egui::Window::new("Vehicle Assembly")
+                .id(egui::Id::new("Editor"))
+                .show(egui_ctx, |ui| {
+                    self.catalog_buttons(ui);
+
+                    ui.add_space(5.0);
+                    ui.separator();
+                    ui.add_space(5.0);
+
+                    self.part_list(ui);
+
+                    ui.add_space(5.0);
+                    ui.separator();
+                    ui.add_space(5.0);
+
+                    self.body_selector(ui);
+                    self.stage_info(ui);
+
+                    ui.add_space(5.0);
+                    ui.separator();
+                    ui.add_space(5.0);
+
+                    let can_launch = self.parts.iter().any(|part| part.kind == PartKind::Engine);
+                    if ui
+                        .add(egui::Button::new("Launch").enabled(can_launch))
+                        .clicked()
+                    {
+                        self.launch_requested = true;
+                    }
+
+                    #[cfg(not(target_arch = "wasm32"))]
+                    self.replay_loader(ui);
+                });
+        });
+
+        egui_macroquad::draw();
+
+        Ok(())
+    }
+
+    fn catalog_buttons(&mut self, ui: &mut egui::Ui) {
+        ui.label("Add part:");
+        ui.horizontal_wrapped(|ui| {
+            for part in self.catalog.iter() {
+                if ui.button(part.name).clicked() {
+                    self.parts.push(part.clone());
+                }
+            }
+        });
+    }
+
+    fn part_list(&mut self, ui: &mut egui::Ui) {
+        let mut move_up = None;
+        let mut remove = None;
+
+        for (i, part) in self.parts.iter().enumerate() {
+            ui.horizontal(|ui| {
+                if ui.add(egui::Button::new("^").enabled(i > 0)).clicked() {
+                    move_up = Some(i);
+                }
+                if ui.button("x").clicked() {
+                    remove = Some(i);
+                }
+                ui.label(format!("{} ({:.0})", part.name, part.total_mass()));
+            });
+        }
+
+        if let Some(i) = move_up {
+            self.parts.swap(i, i - 1);
+        }
+        if let Some(i) = remove {
+            self.parts.remove(i);
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn replay_loader(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Replay file:");
+            ui.text_edit_singleline(&mut self.replay_path);
+            if ui.button("Load Replay").clicked() {
+                match Replay::load(&self.replay_path) {
+                    Ok(replay) => {
+                        self.replay_requested = Some(replay);
+                        self.replay_error = None;
+                    }
+                    Err(error) => self.replay_error = Some(error.to_string()),
+                }
+            }
+        });
+
+        if let Some(error) = &self.replay_error {
+            ui.colored_label(egui::Color32::RED, error);
+        }
+    }
+
+    fn body_selector(&mut self, ui: &mut egui::Ui) {
+        let bodies = &self.bodies;
+        let selected_body = &mut self.selected_body;
+        egui::ComboBox::from_label("TWR at")
+            .selected_text(&bodies[*selected_body].name)
+            .show_ui(ui, |ui| {
+                for (i, body) in bodies.iter().enumerate() {
+                    ui.selectable_value(selected_body, i, &body.name);
+                }
+            });
+    }
+
+    fn stage_info(&self, ui: &mut egui::Ui) {
+        let stages = design_stages(&self.parts);
+        let gravity = self.bodies[self.selected_body].surface_gravity();
+
+        let total_mass: f32 = self.parts.iter().map(|part| part.total_mass()).sum();
+        ui.label(format!("Mass: {:.0}", total_mass));
+
+        egui::Grid::new("stages").striped(true).show(ui, |ui| {
+            ui.label("Stage");
+            ui.label("Mass");
+            ui.label("Delta-v");
+            ui.label("TWR");
+            ui.label("Stability");
+            ui.end_row();
+
+            for (i, stage) in stages.iter().enumerate() {
+                ui.label(format!("{}", i + 1));
+                ui.label(format!("{:.0}", stage.total_mass()));
+                ui.label(format!("{:.0}", stage.delta_v() * 1000.0));
+                ui.label(format!("{:.2}", stage.thrust_to_weight(gravity)));
+                if stage.stability < 0.0 {
+                    ui.colored_label(egui::Color32::RED, "Unstable");
+                } else {
+                    ui.label(format!("{:.3}", stage.stability));
+                }
+                ui.end_row();
+            }
+        });
+
+        let total_delta_v: f32 = stages.iter().map(|stage| stage.delta_v()).sum();
+        ui.label(format!("Total Delta-v: {:.0}", total_delta_v * 1000.0));
+    }
+}
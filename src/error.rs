@@ -1,14 +1,30 @@
 use std::error::Error;
 
 #[derive(Debug)]
-pub enum GameError {}
+pub enum GameError {
+    Io(std::io::Error),
+    InvalidReplay(String),
+    /// a flight was started with a rocket that has no stages
+    NoStages,
+}
+
 impl std::fmt::Display for GameError {
     fn fmt(
         &self,
         formatter: &mut std::fmt::Formatter<'_>,
     ) -> std::result::Result<(), std::fmt::Error> {
-        write!(formatter, "GameError")
+        match self {
+            GameError::Io(error) => write!(formatter, "IO error: {}", error),
+            GameError::InvalidReplay(reason) => write!(formatter, "Invalid replay: {}", reason),
+            GameError::NoStages => write!(formatter, "The rocket has no stages"),
+        }
     }
 }
 
 impl Error for GameError {}
+
+impl From<std::io::Error> for GameError {
+    fn from(error: std::io::Error) -> Self {
+        GameError::Io(error)
+    }
+}
@@ -0,0 +1,36 @@
+use crate::error::GameError;
+
+/// Writes `contents` to `<prefix>_<timestamp>.<extension>` in the working directory
+/// and returns the file's name
+#[cfg(not(target_arch = "wasm32"))]
+pub fn save_text_file(prefix: &str, extension: &str, contents: &str) -> Result<String, GameError> {
+    let seconds = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let file_name = format!("{}_{}.{}", prefix, seconds, extension);
+
+    std::fs::write(&file_name, contents)?;
+    Ok(file_name)
+}
+
+/// Has the browser download `contents` as `<prefix>.<extension>` and returns the file's name
+#[cfg(target_arch = "wasm32")]
+pub fn save_text_file(prefix: &str, extension: &str, contents: &str) -> Result<String, GameError> {
+    let file_name = format!("{}.{}", prefix, extension);
+    download_file(&file_name, contents);
+    Ok(file_name)
+}
+
+#[cfg(target_arch = "wasm32")]
+#[wasm_bindgen::prelude::wasm_bindgen(inline_js = "
+export function download_file(name, contents) {
+    const url = URL.createObjectURL(new Blob([contents], { type: 'text/plain' }));
+    const link = document.createElement('a');
+    link.href = url;
+    link.download = name;
+    link.click();
+    URL.revokeObjectURL(url);
+}")]
+extern "C" {
+    fn download_file(name: &str, contents: &str);
+}
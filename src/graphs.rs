@@ -1,19 +1,157 @@
-use crate::physics::Kinematics;
-use crate::rocket::Rocket;
+use crate::physics::{Kinematics, SimTime, DT};
+use crate::planet::CelestialBody;
+use crate::rocket::{Altitude, Rocket};
+use crate::trajectory::Trajectory;
 use bevy_ecs::prelude::*;
+use egui_macroquad::macroquad::prelude::Vec2;
 
 use std::collections::VecDeque;
 
-pub struct SpeedGraph(pub VecDeque<f32>);
+/// sim seconds between samples, so faster sim speeds don't fill the history quicker
+const SAMPLE_INTERVAL: f32 = 1.0 / 60.0;
+/// longest history that can be shown, in sim seconds
+pub const MAX_WINDOW: f32 = 600.0;
+const SERIES_COUNT: usize = 7;
 
-const MAX_POINTS: usize = 60 * 30;
+#[derive(Copy, Clone, PartialEq)]
+pub enum Series {
+    Altitude,
+    VerticalSpeed,
+    HorizontalSpeed,
+    Acceleration,
+    Fuel,
+    DynamicPressure,
+    Throttle,
+}
+
+impl Series {
+    pub const ALL: [Series; SERIES_COUNT] = [
+        Series::Altitude,
+        Series::VerticalSpeed,
+        Series::HorizontalSpeed,
+        Series::Acceleration,
+        Series::Fuel,
+        Series::DynamicPressure,
+        Series::Throttle,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Series::Altitude => "Altitude",
+            Series::VerticalSpeed => "Vertical Speed",
+            Series::HorizontalSpeed => "Horizontal Speed",
+            Series::Acceleration => "Acceleration",
+            Series::Fuel => "Fuel",
+            Series::DynamicPressure => "Dynamic Pressure",
+            Series::Throttle => "Throttle",
+        }
+    }
+
+    /// what the series is measured in, series sharing a unit are plotted together
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Series::Altitude => "m",
+            Series::VerticalSpeed | Series::HorizontalSpeed => "m/s",
+            Series::Acceleration => "m/s^2",
+            Series::Fuel => "fuel",
+            Series::DynamicPressure => "pressure",
+            Series::Throttle => "throttle",
+        }
+    }
+
+    fn index(&self) -> usize {
+        Series::ALL
+            .iter()
+            .position(|series| series == self)
+            .unwrap()
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct TelemetrySample {
+    /// universal time, in sim seconds
+    pub time: f64,
+    values: [f32; SERIES_COUNT],
+}
+
+impl TelemetrySample {
+    pub fn value(&self, series: Series) -> f32 {
+        self.values[series.index()]
+    }
+}
+
+/// Recent history of the rocket's flight data, covering `MAX_WINDOW` sim seconds
+#[derive(Default)]
+pub struct Telemetry {
+    pub samples: VecDeque<TelemetrySample>,
+    last_vel: Vec2,
+}
+
+/// Which series the graph panel shows and over how long
+pub struct GraphSettings {
+    pub shown: Vec<Series>,
+    /// sim seconds of history to show
+    pub window: f32,
+}
+
+impl Default for GraphSettings {
+    fn default() -> Self {
+        GraphSettings {
+            shown: vec![Series::Altitude],
+            window: 60.0,
+        }
+    }
+}
+
+pub fn rocket_graph_sys(
+    mut query: Query<(&Rocket, &Kinematics, &Altitude, &mut Telemetry), Without<Trajectory>>,
+    planet_query: Query<(&CelestialBody, &Kinematics)>,
+    sim_time: Res<SimTime>,
+    dt: Res<DT>,
+) {
+    let time = sim_time.universal;
+
+    for (rocket, kinematics, altitude, mut telemetry) in query.iter_mut() {
+        // warp can stop before any time has passed
+        let acceleration = if dt.0 > 0.0 {
+            (kinematics.vel - telemetry.last_vel).length() / dt.0
+        } else {
+            0.0
+        };
+        telemetry.last_vel = kinematics.vel;
+
+        if let Some(last) = telemetry.samples.back() {
+            if time - last.time < SAMPLE_INTERVAL as f64 {
+                continue;
+            }
+        }
+
+        let (planet, planet_kinematics) = planet_query.get(altitude.closest_planet).unwrap();
+        let up = (kinematics.pos - planet_kinematics.pos).normalize();
+        let vel = kinematics.vel - planet_kinematics.vel;
+        let air_speed =
+            (kinematics.vel - planet.air_velocity_at(planet_kinematics, kinematics.pos)).length();
+
+        let mut values = [0.0; SERIES_COUNT];
+        values[Series::Altitude.index()] = altitude.height * 1000.0;
+        values[Series::VerticalSpeed.index()] = vel.dot(up) * 1000.0;
+        values[Series::HorizontalSpeed.index()] = vel.perp_dot(up).abs() * 1000.0;
+        values[Series::Acceleration.index()] = acceleration * 1000.0;
+        values[Series::Fuel.index()] = rocket.current_fuel_mass;
+        values[Series::DynamicPressure.index()] =
+            0.5 * planet.atmosphere_density(altitude.height) * air_speed * air_speed;
+        values[Series::Throttle.index()] = rocket.thrust;
 
-pub fn rocket_graph_sys(mut query: Query<(&Rocket, &Kinematics, &mut SpeedGraph)>) {
-    for (_, kinematics, mut speed_graph) in query.iter_mut() {
-        speed_graph.0.push_back(kinematics.vel.length());
+        telemetry
+            .samples
+            .push_back(TelemetrySample { time, values });
 
-        while speed_graph.0.len() > MAX_POINTS {
-            speed_graph.0.pop_front();
+        while telemetry
+            .samples
+            .front()
+            .is_some_and(|sample| time - sample.time > MAX_WINDOW as f64)
+        {
+            telemetry.samples.pop_front();
         }
     }
 }
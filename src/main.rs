@@ -4,14 +4,21 @@ use macroquad::prelude::*;
 pub mod error;
 use error::GameError;
 
+pub mod actions;
 pub mod camera;
+pub mod editor;
+mod files;
 pub mod graphs;
 pub mod main_state;
 pub mod map;
 pub mod physics;
 pub mod planet;
+mod recorder;
+pub mod replay;
 pub mod rocket;
+pub mod terrain;
 pub mod texture;
+mod trail;
 pub mod trajectory;
 pub mod ui;
 
@@ -24,12 +31,43 @@ pub const GRAVITY: f32 = 5.0;
 #[macroquad::main("Rocket")]
 async fn main() -> Result<(), GameError> {
     next_frame().await;
-    let mut main_state = main_state::MainState::new();
+    let mut editor_state = editor::EditorState::new();
+    let mut main_state: Option<main_state::MainState> = None;
+    let mut action_map = actions::ActionMap::load();
 
     loop {
-        main_state.update()?;
-        main_state.draw()?;
-        main_state.draw_ui()?;
+        match main_state.as_mut() {
+            Some(state) => {
+                state.update()?;
+                state.draw()?;
+                state.draw_ui()?;
+
+                if state.exit_to_editor {
+                    action_map = state.action_map();
+                    main_state = None;
+                } else if let Some(replay) = state.replay_requested.take() {
+                    action_map = state.action_map();
+                    main_state = Some(main_state::MainState::from_replay(
+                        replay,
+                        action_map.clone(),
+                    )?);
+                }
+            }
+            None => {
+                editor_state.update()?;
+                editor_state.draw()?;
+                editor_state.draw_ui()?;
+
+                if let Some(stages) = editor_state.take_launch() {
+                    main_state = Some(main_state::MainState::new(stages, action_map.clone())?);
+                } else if let Some(replay) = editor_state.take_replay() {
+                    main_state = Some(main_state::MainState::from_replay(
+                        replay,
+                        action_map.clone(),
+                    )?);
+                }
+            }
+        }
 
         next_frame().await
     }
@@ -4,9 +4,14 @@ use bevy_ecs::world::World;
 
 use egui_macroquad::macroquad::prelude::*;
 
+use crate::actions::{Action, ActionMap};
 use crate::graphs;
-use crate::physics::{self, DT};
-use crate::rocket::{self, RocketBundle, RocketEntity};
+use crate::physics::step::{AdaptiveStep, BASE_DT, FRAME_BUDGET};
+use crate::physics::warp::{TimeWarp, MAX_WARP_FRAME_TIME, WARP_SUBSTEPS};
+use crate::physics::{self, Kinematics, SimControl, SimTime, DT};
+use crate::planet::CelestialBody;
+use crate::replay::{InputCommands, Replay, ReplayState};
+use crate::rocket::{self, Rocket, RocketBundle, RocketEntity, Stages};
 use crate::GameError;
 
 use crate::camera;
@@ -17,33 +22,98 @@ pub struct MainState {
     fixed_schedule: Schedule,
     draw_schedule: Schedule,
     pub trajectory_schedule: Schedule,
-    leftover_time: f32,
+    /// runs in place of `fixed_schedule` during on-rails time warp
+    warp_schedule: Schedule,
+    pub exit_to_editor: bool,
+    /// set to restart the flight as a playback of this replay
+    pub replay_requested: Option<Replay>,
+    pub keybindings_open: bool,
+    /// action waiting for a key press to bind to it
+    pub rebinding: Option<Action>,
+    pub keybindings_status: Option<String>,
 }
 
 impl MainState {
-    pub fn new() -> Self {
+    /// `stages` are in firing order, the first one is active at launch
+    pub fn new(stages: Vec<Rocket>, action_map: ActionMap) -> Result<Self, GameError> {
+        let (first_stage, later_stages) = match stages.split_first() {
+            Some((first_stage, later_stages)) => (*first_stage, later_stages.to_vec()),
+            None => return Err(GameError::NoStages),
+        };
+
         let mut world = World::new();
+        world.insert_resource(action_map);
+        world.insert_resource(ReplayState::recording(stages.clone()));
+        world.insert_resource(InputCommands::default());
+        world.insert_resource(crate::ui::PointerOverUi::default());
 
         let mut fixed_schedule = Schedule::default();
         fixed_schedule.add_stage(
             "physics",
             SystemStage::single_threaded()
-                .with_system(physics::rocket_thrust_sys.system().label("thrust"))
+                .with_system(rocket::rocket_command_sys.system().label("input"))
+                .with_system(rocket::rocket_sas_sys.system().after("input"))
+                .with_system(
+                    physics::rocket_thrust_sys
+                        .system()
+                        .label("thrust")
+                        .after("input"),
+                )
                 .with_system(
                     physics::rocket_planet_interaction_sys
                         .system()
+                        .label("planets")
                         .after("thrust"),
-                ),
+                )
+                .with_system(
+                    physics::gforce::rocket_g_force_sys
+                        .system()
+                        .after("planets"),
+                )
+                .with_system(physics::step::adaptive_step_sys.system().after("planets")),
         );
         fixed_schedule.add_stage_after(
             "physics",
             "integrate",
             SystemStage::single_threaded()
                 .with_system(physics::integration_sys.system().label("integrate"))
+                .with_system(physics::rotation_sys.system())
                 .with_system(physics::reset_accel_sys.system().after("integrate"))
-                .with_system(graphs::rocket_graph_sys.system().after("integrate"))
-                .with_system(rocket::update_altitude_sys.system().after("integrate"))
-                .with_system(physics::rocket_crash_sys.system().after("integrate"))
+                .with_system(physics::sim_time_sys.system().label("time"))
+                .with_system(
+                    physics::planet_rotation_sys
+                        .system()
+                        .label("bodies")
+                        .after("time"),
+                )
+                .with_system(graphs::rocket_graph_sys.system().after("altitude"))
+                .with_system(
+                    rocket::update_altitude_sys
+                        .system()
+                        .label("altitude")
+                        .after("integrate")
+                        .after("bodies"),
+                )
+                .with_system(
+                    crate::recorder::record_flight_sys
+                        .system()
+                        .after("altitude"),
+                )
+                .with_system(
+                    physics::rocket_crash_sys
+                        .system()
+                        .label("crash")
+                        .after("integrate")
+                        .after("bodies"),
+                )
+                .with_system(
+                    physics::rocket_stranded_sys
+                        .system()
+                        .after("crash")
+                        .after("altitude"),
+                )
+                .with_system(physics::heat::rocket_heating_sys.system().after("altitude"))
+                .with_system(crate::trail::record_trail_sys.system().after("integrate"))
                 .with_system(physics::offset::update_offset_sys.system()),
         );
 
@@ -65,6 +135,7 @@ impl MainState {
                         .system()
                         .label("integrate"),
                 )
+                .with_system(physics::trajectory_rotation_sys.system())
                 .with_system(
                     physics::trajectory_reset_accel_sys
                         .system()
@@ -72,6 +143,39 @@ impl MainState {
                 ),
         );
 
+        let mut warp_schedule = Schedule::default();
+        warp_schedule.add_stage(
+            "warp",
+            SystemStage::single_threaded()
+                .with_system(physics::warp::rocket_warp_sys.system().label("warp"))
+                .with_system(physics::sim_time_sys.system().label("time").after("warp"))
+                .with_system(
+                    physics::planet_rotation_sys
+                        .system()
+                        .label("bodies")
+                        .after("time"),
+                )
+                .with_system(
+                    rocket::update_altitude_sys
+                        .system()
+                        .label("altitude")
+                        .after("warp")
+                        .after("bodies"),
+                )
+                .with_system(graphs::rocket_graph_sys.system().after("altitude"))
+                .with_system(
+                    crate::recorder::record_flight_sys
+                        .system()
+                        .after("altitude"),
+                )
+                .with_system(crate::trail::record_trail_sys.system().after("warp"))
+                .with_system(
+                    physics::offset::update_offset_sys
+                        .system()
+                        .after("altitude"),
+                ),
+        );
+
         let mut frame_schedule = Schedule::default();
         frame_schedule.add_stage(
             "camera",
@@ -83,7 +187,11 @@ impl MainState {
             "input",
             SystemStage::single_threaded()
                 .with_system(crate::map::map_input_sys.system())
-                .with_system(crate::rocket::rocket_input_sys.system()),
+                .with_system(camera::camera_zoom_input_sys.system())
+                .with_system(camera::camera_mode_input_sys.system())
+                .with_system(crate::rocket::rocket_input_sys.system())
+                .with_system(physics::sim_control_input_sys.system())
+                .with_system(physics::warp::warp_input_sys.system()),
         );
 
         let mut draw_schedule = Schedule::default();
@@ -101,11 +209,17 @@ impl MainState {
                         .after("atmosphere")
                         .label("planets"),
                 )
+                .with_system(
+                    crate::trail::draw_trail_sys
+                        .system()
+                        .label("trail")
+                        .after("planets"),
+                )
                 .with_system(
                     crate::rocket::draw_rocket_sys
                         .system()
                         .label("rocket")
-                        .after("planets"),
+                        .after("trail"),
                 )
                 .with_system(
                     crate::map::draw_map_sys
@@ -116,34 +230,89 @@ impl MainState {
                 .with_system(draw_crashed_text_sys.system().label("crashed").after("map")),
         );
 
-        let rocket = world.spawn().insert_bundle(RocketBundle::default()).id();
+        crate::planet::add_planets(&mut world);
+
+        // start out on the ground of the closest body, moving along with it
+        let launch_kinematics = {
+            let pos = RocketBundle::default().kinematics.pos;
+            let mut planet_query = world.query::<(&CelestialBody, &Kinematics)>();
+            let (planet, planet_kinematics) = planet_query
+                .iter(&world)
+                .min_by(|(a, a_kinematics), (b, b_kinematics)| {
+                    let a_altitude = (pos - a_kinematics.pos).length() - a.radius;
+                    let b_altitude = (pos - b_kinematics.pos).length() - b.radius;
+                    a_altitude.partial_cmp(&b_altitude).unwrap()
+                })
+                .unwrap();
+
+            let up = (pos - planet_kinematics.pos).normalize();
+            let pos = planet_kinematics.pos
+                + up * (planet.surface_radius_at(planet_kinematics, pos) + 0.5);
+
+            Kinematics {
+                pos,
+                vel: planet.air_velocity_at(planet_kinematics, pos),
+                ..Kinematics::default()
+            }
+        };
+
+        let rocket_bundle = || RocketBundle {
+            kinematics: launch_kinematics,
+            rocket: first_stage,
+            stages: Stages(later_stages.clone()),
+            ..RocketBundle::default()
+        };
+
+        let rocket = world.spawn().insert_bundle(rocket_bundle()).id();
         world.insert_resource(RocketEntity(rocket));
 
         world
             .spawn()
-            .insert_bundle(RocketBundle::default())
+            .insert_bundle(rocket_bundle())
             .insert(crate::trajectory::Trajectory::new(20 * 60))
             .id();
 
         world.insert_resource(crate::camera::CameraRes::default());
         world.insert_resource(crate::physics::Steps(1));
+        world.insert_resource(SimControl::default());
+        world.insert_resource(SimTime::default());
+        world.insert_resource(TimeWarp::default());
         world.insert_resource(DT(1.0 / 60.0));
+        world.insert_resource(AdaptiveStep::default());
         world.insert_resource(crate::map::MapRes::default());
         world.insert_resource(crate::texture::Textures::default());
-        world.insert_resource(crate::rocket::RocketCrashed(false));
+        world.insert_resource(crate::rocket::FlightOutcome::InFlight);
         world.insert_resource(crate::trajectory::TrajectorySyncClock::default());
         world.insert_resource(crate::physics::offset::UniverseOffset::default());
+        world.insert_resource(crate::trail::FlightTrail::default());
+        world.insert_resource(crate::recorder::FlightRecorder::default());
+        world.insert_resource(graphs::GraphSettings::default());
 
-        crate::planet::add_planets(&mut world);
-
-        MainState {
+        Ok(MainState {
             world,
             frame_schedule,
             fixed_schedule,
             trajectory_schedule,
+            warp_schedule,
             draw_schedule,
-            leftover_time: 0.0,
-        }
+            exit_to_editor: false,
+            replay_requested: None,
+            keybindings_open: false,
+            rebinding: None,
+            keybindings_status: None,
+        })
+    }
+
+    /// Starts the replay's flight over, with its recorded input in place of the player's
+    pub fn from_replay(replay: Replay, action_map: ActionMap) -> Result<Self, GameError> {
+        let mut state = MainState::new(replay.stages.clone(), action_map)?;
+        state.world.insert_resource(ReplayState::playback(replay));
+        Ok(state)
+    }
+
+    /// The key bindings, possibly changed during the flight, to carry over to the next one
+    pub fn action_map(&self) -> ActionMap {
+        self.world.get_resource::<ActionMap>().unwrap().clone()
     }
 
     pub fn draw(&mut self) -> Result<(), GameError> {
@@ -151,46 +320,150 @@ impl MainState {
         Ok(())
     }
 
+    /// Moves the rocket on rails for `duration` sim seconds, in substeps so it stops
+    /// near whatever ended the warp
+    fn warp(&mut self, duration: f32) {
+        let level = self.world.get_resource::<TimeWarp>().unwrap().level;
+        self.world
+            .get_resource_mut::<ReplayState>()
+            .unwrap()
+            .record_warp(level, duration);
+
+        for _ in 0..WARP_SUBSTEPS {
+            // the warp cuts `DT` short when it stops partway
+            self.world
+                .insert_resource(DT(duration / WARP_SUBSTEPS as f32));
+            self.warp_schedule.run(&mut self.world);
+            if !self.world.get_resource::<TimeWarp>().unwrap().is_active() {
+                break;
+            }
+        }
+
+        self.world.insert_resource(DT(BASE_DT));
+        self.world.insert_resource(AdaptiveStep::default());
+        // the prediction was made from where the rocket was before warping
+        self.reset_trajectory();
+    }
+
+    /// Plays a frame of warp if the replay warps before the next tick, returning
+    /// whether it did
+    fn replayed_warp(&mut self) -> bool {
+        let mut replay = self.world.get_resource_mut::<ReplayState>().unwrap();
+        let (level, duration) = match replay.replayed_warp() {
+            Some(warp) => warp,
+            None => return false,
+        };
+
+        self.world.get_resource_mut::<TimeWarp>().unwrap().level = level;
+        self.warp(duration);
+        // only the replay warps while it's playing
+        self.world.get_resource_mut::<TimeWarp>().unwrap().level = 0;
+        true
+    }
+
+    /// Runs one physics tick as long as `AdaptiveStep` picked, up to `max_dt`, or as
+    /// long as it was when a replay was recorded. Returns how long it turned out to be.
+    fn run_tick(&mut self, max_dt: f32) -> f32 {
+        // when stepping through a replay, any warp before the tick has to happen first
+        while self.replayed_warp() {}
+
+        let adaptive_dt = self.world.get_resource::<AdaptiveStep>().unwrap().dt;
+        let dt = self
+            .world
+            .get_resource_mut::<ReplayState>()
+            .unwrap()
+            .replayed_tick_length()
+            .unwrap_or_else(|| adaptive_dt.min(max_dt));
+        self.world.insert_resource(DT(dt));
+        self.fixed_schedule.run(&mut self.world);
+
+        // input during the tick cuts it back to `BASE_DT`
+        let dt = self.world.get_resource::<DT>().unwrap().0;
+        self.world
+            .get_resource_mut::<ReplayState>()
+            .unwrap()
+            .record_tick_length(dt);
+        dt
+    }
+
     pub fn update(&mut self) -> Result<(), GameError> {
+        let mut time_run = 0.0;
         if !self
             .world
-            .get_resource::<crate::rocket::RocketCrashed>()
+            .get_resource::<crate::rocket::FlightOutcome>()
             .unwrap()
-            .0
+            .is_over()
         {
-            let dt = self.world.get_resource::<DT>().unwrap().0;
-            let steps = self
-                .world
-                .get_resource::<crate::physics::Steps>()
-                .unwrap()
-                .0;
-            let target_dt = steps as f32 / 60.0 + self.leftover_time;
-            let mut acc_time = 0.0;
-            while acc_time < target_dt {
-                self.fixed_schedule.run(&mut self.world);
-                acc_time += dt;
+            let mut sim_control = self.world.get_resource_mut::<SimControl>().unwrap();
+            if sim_control.paused {
+                let pending_ticks = std::mem::take(&mut sim_control.pending_ticks);
+                // stepping goes one base tick at a time, however long ticks have grown
+                for _ in 0..pending_ticks {
+                    time_run += self.run_tick(BASE_DT);
+                }
+            } else if self.replayed_warp() {
+                // the frame went to the replay's warp
+            } else if self.world.get_resource::<TimeWarp>().unwrap().is_active() {
+                let factor = self.world.get_resource::<TimeWarp>().unwrap().factor();
+                self.warp(factor * get_frame_time().min(MAX_WARP_FRAME_TIME));
+            } else {
+                // counted in `BASE_DT`s, which every tick is a whole number of
+                let mut base_ticks_left = self
+                    .world
+                    .get_resource::<crate::physics::Steps>()
+                    .unwrap()
+                    .0;
+                let start_time = get_time();
+                // time that doesn't fit in the budget is dropped so the frame rate holds up
+                while base_ticks_left > 0
+                    && get_time() - start_time < FRAME_BUDGET
+                    && !self
+                        .world
+                        .get_resource::<ReplayState>()
+                        .unwrap()
+                        .warp_pending()
+                {
+                    let dt = self.run_tick(base_ticks_left as f32 * BASE_DT);
+                    let base_ticks = (dt / BASE_DT).round() as usize;
+                    base_ticks_left = base_ticks_left.saturating_sub(base_ticks.max(1));
+                    time_run += dt;
+                }
             }
-            self.leftover_time = acc_time - target_dt;
+            self.world.insert_resource(DT(BASE_DT));
         }
 
-        self.add_trajectory_points();
+        // keeps predicting while paused, so it can be inspected
+        self.add_trajectory_points(time_run);
         self.frame_schedule.run(&mut self.world);
         Ok(())
     }
 }
 
 pub fn draw_crashed_text_sys(
-    rocket_crashed: bevy_ecs::prelude::Res<crate::rocket::RocketCrashed>,
+    flight_outcome: bevy_ecs::prelude::Res<crate::rocket::FlightOutcome>,
     camera_res: bevy_ecs::prelude::Res<crate::camera::CameraRes>,
 ) {
-    if rocket_crashed.0 {
+    let zoom_level = camera_res.zoom_level;
+
+    if flight_outcome.is_over() {
         draw_text_ex(
-            "CRASHED",
-            camera_res.camera.target.x - crate::SCREEN_WIDTH / 2.15,
+            flight_outcome.title(),
+            camera_res.camera.target.x - crate::SCREEN_WIDTH / 2.15 * zoom_level,
             camera_res.camera.target.y,
             TextParams {
                 font_size: 48,
-                font_scale: 1.0 / 64.0,
+                font_scale: zoom_level / 64.0,
+                color: RED,
+                ..Default::default()
+            },
+        );
+        draw_text_ex(
+            &flight_outcome.description(),
+            camera_res.camera.target.x - crate::SCREEN_WIDTH / 2.15 * zoom_level,
+            camera_res.camera.target.y - crate::SCREEN_HEIGHT / 10.0 * zoom_level,
+            TextParams {
+                font_size: 24,
+                font_scale: zoom_level / 64.0,
                 color: RED,
                 ..Default::default()
             },
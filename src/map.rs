@@ -1,19 +1,59 @@
 use egui_macroquad::macroquad::prelude::*;
 
-use crate::camera::CameraRes;
+use crate::actions::{Action, ActionMap};
+use crate::camera::{curve_segments, CameraRes};
+use crate::physics::offset::UniverseOffset;
+use crate::physics::orbit::Orbit;
 use crate::physics::Kinematics;
-use crate::planet::CelestialBody;
+use crate::planet::{CelestialBody, SpheresOfInfluence};
 use crate::texture::Textures;
+use crate::trail::FlightTrail;
 use crate::trajectory::Trajectory;
+use crate::ui::PointerOverUi;
 
-use crate::rocket::{draw_rocket, Rocket, RocketEntity};
+use crate::rocket::{draw_rocket, Altitude, Rocket, RocketEntity};
 
 use bevy_ecs::prelude::*;
 
+/// world units shown across the height of the map at scale 1
+const MAP_VIEW_HEIGHT: f32 = 50_000.0;
+pub const MIN_MAP_SCALE: f32 = 0.0005;
+pub const MAX_MAP_SCALE: f32 = 100.0;
+
+/// how much one notch of the mouse wheel zooms
+const WHEEL_ZOOM_FACTOR: f32 = 1.25;
+/// how much holding Up/Down zooms per second
+const KEY_ZOOM_FACTOR: f32 = 4.0;
+/// longest time between two clicks for them to count as a double click, in seconds
+const DOUBLE_CLICK_TIME: f64 = 0.3;
+/// how close a double click has to be to the rocket, as a proportion of the map's height
+const CLICK_TOLERANCE: f32 = 0.02;
+/// number of altitude rings drawn around the focused body
+const RING_COUNT: usize = 8;
+const LABEL_SIZE: f32 = 20.0;
+
+const SOI_COLOR: Color = Color::new(0.6, 0.4, 1.0, 0.8);
+/// trajectory color where it passes through an atmosphere
+const ATMOSPHERE_TRAJECTORY_COLOR: Color = ORANGE;
+/// trajectory color once it's left the sphere of influence it started in
+const ESCAPED_TRAJECTORY_COLOR: Color = YELLOW;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum MapFocus {
+    Rocket,
+    Body(Entity),
+}
+
 pub struct MapRes {
+    /// offset of the center of the map from the focus
     pub position: Vec2,
     pub scale: f32,
     pub shown: bool,
+    pub focus: MapFocus,
+    /// whether to draw altitude rings around the focused body
+    pub show_rings: bool,
+    last_mouse_pos: Vec2,
+    last_click_time: f64,
 }
 
 impl Default for MapRes {
@@ -22,125 +62,380 @@ impl Default for MapRes {
             position: Vec2::new(0.0, 0.0),
             scale: 1.0,
             shown: false,
+            focus: MapFocus::Rocket,
+            show_rings: false,
+            last_mouse_pos: Vec2::new(0.0, 0.0),
+            last_click_time: 0.0,
+        }
+    }
+}
+
+impl MapRes {
+    /// height of the map in world units
+    pub fn view_height(&self) -> f32 {
+        MAP_VIEW_HEIGHT * self.scale
+    }
+
+    /// A camera looking at the map, in world coordinates
+    pub fn camera(&self, focus_pos: Vec2) -> Camera2D {
+        let zoom_y = 2.0 / self.view_height();
+        Camera2D {
+            target: focus_pos + self.position,
+            zoom: Vec2::new(zoom_y * screen_height() / screen_width(), zoom_y),
+            ..Camera2D::default()
         }
     }
+
+    pub fn focus_position(&self, rocket_entity: Entity, kinematics: &Query<&Kinematics>) -> Vec2 {
+        let focus_entity = match self.focus {
+            MapFocus::Rocket => rocket_entity,
+            MapFocus::Body(body) => body,
+        };
+
+        kinematics
+            .get(focus_entity)
+            .or_else(|_| kinematics.get(rocket_entity))
+            .unwrap()
+            .pos
+    }
+}
+
+/// Largest 1, 2 or 5 times a power of ten that's at most `max`
+fn nice_length(max: f32) -> f32 {
+    let magnitude = 10f32.powf(max.log10().floor());
+    if magnitude * 5.0 <= max {
+        magnitude * 5.0
+    } else if magnitude * 2.0 <= max {
+        magnitude * 2.0
+    } else {
+        magnitude
+    }
+}
+
+/// world units are kilometers
+fn format_distance(distance: f32) -> String {
+    if distance.abs() >= 1.0 {
+        format!("{:.0} km", distance)
+    } else {
+        format!("{:.0} m", distance * 1000.0)
+    }
 }
 
+/// Draws the part of a circle that's around the view, with enough segments for its
+/// length on screen that it stays round at any zoom
+pub fn draw_ring(
+    center: Vec2,
+    radius: f32,
+    view_center: Vec2,
+    view_radius: f32,
+    thickness: f32,
+    color: Color,
+) {
+    let to_view = view_center - center;
+    if radius < to_view.length() - view_radius || radius > to_view.length() + view_radius {
+        return;
+    }
+
+    let center_angle = to_view.y.atan2(to_view.x);
+    let half_span = (view_radius * 2.0 / radius).min(std::f32::consts::PI);
+    let segments = curve_segments(radius * half_span * 2.0, view_radius);
+    let step = half_span * 2.0 / segments as f32;
+
+    let point_at = |angle: f32| center + Vec2::new(angle.cos(), angle.sin()) * radius;
+    let mut prev = point_at(center_angle - half_span);
+    for i in 1..=segments {
+        let next = point_at(center_angle - half_span + step * i as f32);
+        draw_line(prev.x, prev.y, next.x, next.y, thickness, color);
+        prev = next;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn draw_map_sys(
-    map_res: ResMut<MapRes>,
+    map_res: Res<MapRes>,
     camera_res: Res<CameraRes>,
-    planet_query: Query<(&CelestialBody, &Kinematics)>,
+    planet_query: Query<(Entity, &CelestialBody, &Kinematics)>,
+    kinematics_query: Query<&Kinematics>,
     trajectory_query: Query<&Trajectory>,
     textures: Res<Textures>,
-    rocket_query: Query<&Rocket>,
+    rocket_query: Query<(&Rocket, &Altitude)>,
     rocket_entity: Res<RocketEntity>,
+    (trail, offset): (Res<FlightTrail>, Res<UniverseOffset>),
 ) {
-    let scale: f32 = 1.0 / 20_000.0 / map_res.scale;
-    let camera_pos = camera_res.camera.target;
-
-    if map_res.shown {
-        let width = crate::SCREEN_WIDTH * 0.8;
-        let height = crate::SCREEN_HEIGHT * 0.8;
-        draw_rectangle(
-            camera_res.camera.target.x - width / 2.0,
-            camera_res.camera.target.y - height / 2.0,
-            width,
-            height,
-            BLACK,
-        );
+    if !map_res.shown {
+        return;
+    }
+
+    let focus_pos = map_res.focus_position(rocket_entity.0, &kinematics_query);
+    let map_camera = map_res.camera(focus_pos);
+    set_camera(&map_camera);
+    clear_background(BLACK);
+
+    let view_height = map_res.view_height();
+    let view_center = map_camera.target;
+    let view_radius = view_height * screen_width() / screen_height();
 
-        draw_rectangle_lines(
-            camera_res.camera.target.x - width / 2.0,
-            camera_res.camera.target.y - height / 2.0,
-            width,
-            height,
-            0.01,
-            RED,
+    let rocket_kinematics = kinematics_query.get(rocket_entity.0).unwrap();
+    let (rocket, rocket_altitude) = rocket_query.get(rocket_entity.0).unwrap();
+
+    for (_, planet, kinematics) in planet_query.iter() {
+        let size = planet.radius * 2.0;
+        draw_texture_ex(
+            textures[planet.texture],
+            kinematics.pos.x - size / 2.0,
+            kinematics.pos.y - size / 2.0,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(Vec2::new(size, size)),
+                rotation: planet.rotation,
+                flip_y: true,
+                ..DrawTextureParams::default()
+            },
         );
+    }
 
-        // rocket
-        if map_res.scale > 2.0 {
-            // red dot
-            draw_circle(
-                camera_res.camera.target.x,
-                camera_res.camera.target.y,
-                crate::SCREEN_WIDTH / 250.0,
-                RED,
-            );
-        } else {
-            // rocket texture
-            let rocket_size = crate::SCREEN_WIDTH / 125.0 / map_res.scale;
-            let rocket = rocket_query.get(rocket_entity.0).unwrap();
-            draw_rocket(
-                &camera_res.camera.target,
-                rocket.angle,
-                rocket.thrust > 0.0 && rocket.current_fuel_mass > 0.0,
-                &textures,
-                rocket_size,
+    // the focused body, or the one the rocket is closest to
+    let reference_body = match map_res.focus {
+        MapFocus::Body(body) => body,
+        MapFocus::Rocket => rocket_altitude.closest_planet,
+    };
+
+    if map_res.show_rings {
+        if let Ok((_, planet, kinematics)) = planet_query.get(reference_body) {
+            let spacing = nice_length(view_height / RING_COUNT as f32);
+            for i in 1..=RING_COUNT {
+                draw_ring(
+                    kinematics.pos,
+                    planet.radius + spacing * i as f32,
+                    view_center,
+                    view_radius,
+                    view_height / 1000.0,
+                    DARKGRAY,
+                );
+            }
+        }
+    }
+
+    let spheres = SpheresOfInfluence::new(planet_query.iter());
+
+    for (_, planet, kinematics) in planet_query.iter() {
+        if planet.has_atmosphere() {
+            draw_ring(
+                kinematics.pos,
+                planet.atmosphere_radius,
+                view_center,
+                view_radius,
+                view_height / 700.0,
+                planet.atmosphere_color,
             );
         }
+    }
 
-        let (x_bounds, y_bounds) = {
-            let x_bounds = (camera_pos.x - width / 2.0)..(camera_pos.x + width / 2.0);
-            let y_bounds = (camera_pos.y - height / 2.0)..(camera_pos.y + height / 2.0);
-            (x_bounds, y_bounds)
-        };
+    for sphere in spheres.spheres.iter() {
+        draw_ring(
+            sphere.center,
+            sphere.radius,
+            view_center,
+            view_radius,
+            view_height / 700.0,
+            SOI_COLOR,
+        );
+    }
 
-        let in_map = |point: &Vec2| x_bounds.contains(&point.x) && y_bounds.contains(&point.y);
-
-        for (planet, kinematics) in planet_query.iter() {
-            let mut offset = camera_pos - kinematics.pos;
-            offset.y *= -1.0;
-            let pos = offset * scale + camera_pos;
-
-            let radius_vec = Vec2::splat(planet.radius * scale);
-            if in_map(&(pos - radius_vec)) || in_map(&(pos + radius_vec)) {
-                let size = planet.radius * 2.0 * scale;
-                draw_texture_ex(
-                    textures[planet.texture],
-                    pos.x - size / 2.0,
-                    pos.y - size / 2.0,
-                    WHITE,
-                    DrawTextureParams {
-                        dest_size: Some(Vec2::new(size, size)),
-                        ..DrawTextureParams::default()
-                    },
-                );
-            }
+    trail.draw(offset.offset, rocket_kinematics.pos, view_height / 700.0);
+
+    let starting_body = spheres.dominant_body(rocket_kinematics.pos);
+    let in_atmosphere = |point: Vec2| {
+        planet_query.iter().any(|(_, planet, kinematics)| {
+            planet.has_atmosphere() && (point - kinematics.pos).length() < planet.atmosphere_radius
+        })
+    };
+
+    for trajectory in trajectory_query.iter() {
+        let fst_iter = trajectory.points.iter();
+        let snd_iter = trajectory.points.iter().skip(1);
+
+        for (fst, snd) in fst_iter.zip(snd_iter) {
+            let color = if in_atmosphere(*snd) {
+                ATMOSPHERE_TRAJECTORY_COLOR
+            } else if spheres.dominant_body(*snd) != starting_body {
+                ESCAPED_TRAJECTORY_COLOR
+            } else {
+                GREEN
+            };
+            draw_line(fst.x, fst.y, snd.x, snd.y, view_height / 500.0, color);
         }
+    }
 
-        for trajectory in trajectory_query.iter() {
-            let fst_iter = trajectory.points.iter();
-            let snd_iter = trajectory.points.iter().skip(1);
+    draw_rocket(
+        &rocket_kinematics.pos,
+        rocket.angle,
+        rocket.thrust > 0.0 && rocket.current_fuel_mass > 0.0,
+        &textures,
+        view_height / 100.0,
+    );
 
-            for (fst, snd) in fst_iter.zip(snd_iter) {
-                let mut fst_offset = camera_pos - *fst;
-                fst_offset.y *= -1.0;
+    // labels are drawn in screen space so the text stays upright and the same size
+    let mut labels: Vec<(Vec2, String, Color)> = planet_query
+        .iter()
+        .map(|(_, planet, kinematics)| {
+            let top = kinematics.pos + Vec2::new(0.0, planet.radius);
+            (top, planet.name.clone(), WHITE)
+        })
+        .collect();
 
-                let mut snd_offset = camera_pos - *snd;
-                snd_offset.y *= -1.0;
+    if let Ok((_, planet, planet_kinematics)) = planet_query.get(rocket_altitude.closest_planet) {
+        let orbit = Orbit::from_state(
+            rocket_kinematics.pos - planet_kinematics.pos,
+            rocket_kinematics.vel - planet_kinematics.vel,
+            crate::GRAVITY * planet.mass,
+        );
 
-                let fst = fst_offset * scale + camera_pos;
-                let snd = snd_offset * scale + camera_pos;
+        if orbit.periapsis_direction.length_squared() > 0.0 {
+            let periapsis_pos = planet_kinematics.pos + orbit.periapsis_direction * orbit.periapsis;
+            labels.push((
+                periapsis_pos,
+                format!("Pe {}", format_distance(orbit.periapsis - planet.radius)),
+                SKYBLUE,
+            ));
 
-                if in_map(&fst) && in_map(&snd) {
-                    draw_line(fst.x, fst.y, snd.x, snd.y, 0.005 / map_res.scale, GREEN);
-                }
+            if let Some(apoapsis) = orbit.apoapsis {
+                let apoapsis_pos = planet_kinematics.pos - orbit.periapsis_direction * apoapsis;
+                labels.push((
+                    apoapsis_pos,
+                    format!("Ap {}", format_distance(apoapsis - planet.radius)),
+                    ORANGE,
+                ));
             }
         }
     }
+
+    set_default_camera();
+
+    for (pos, text, color) in labels.iter() {
+        let screen_pos = map_camera.world_to_screen(*pos);
+        draw_circle(screen_pos.x, screen_pos.y, 3.0, *color);
+        draw_text(
+            text,
+            screen_pos.x + 6.0,
+            screen_pos.y - 6.0,
+            LABEL_SIZE,
+            *color,
+        );
+    }
+
+    draw_scale_bar(view_height);
+
+    set_camera(&camera_res.camera);
 }
 
-pub fn map_input_sys(mut map_res: ResMut<MapRes>) {
-    if is_key_pressed(KeyCode::M) {
+/// Draws a bar of a round length in the bottom left corner, in screen space
+fn draw_scale_bar(view_height: f32) {
+    let world_per_pixel = view_height / screen_height();
+    let length = nice_length(world_per_pixel * screen_width() / 5.0);
+    let pixels = length / world_per_pixel;
+
+    let x = 20.0;
+    let y = screen_height() - 20.0;
+    draw_line(x, y, x + pixels, y, 2.0, WHITE);
+    draw_line(x, y - 5.0, x, y + 5.0, 2.0, WHITE);
+    draw_line(x + pixels, y - 5.0, x + pixels, y + 5.0, 2.0, WHITE);
+    draw_text(&format_distance(length), x, y - 10.0, LABEL_SIZE, WHITE);
+}
+
+pub fn map_input_sys(
+    mut map_res: ResMut<MapRes>,
+    kinematics_query: Query<&Kinematics>,
+    planet_query: Query<(Entity, &CelestialBody, &Kinematics)>,
+    rocket_entity: Res<RocketEntity>,
+    action_map: Res<ActionMap>,
+    pointer_over_ui: Res<PointerOverUi>,
+) {
+    if action_map.is_pressed(Action::ToggleMap) {
         map_res.shown = !map_res.shown;
     }
 
-    if is_key_down(KeyCode::Up) {
-        map_res.scale = map_res.scale + 0.25;
+    if action_map.is_pressed(Action::ToggleMapRings) {
+        map_res.show_rings = !map_res.show_rings;
+    }
+
+    let mouse_pos = Vec2::from(mouse_position());
+    let last_mouse_pos = map_res.last_mouse_pos;
+    map_res.last_mouse_pos = mouse_pos;
+
+    if !map_res.shown {
+        return;
+    }
+
+    let focus_pos = map_res.focus_position(rocket_entity.0, &kinematics_query);
+    let camera = map_res.camera(focus_pos);
+    let mouse_world_pos = camera.screen_to_world(mouse_pos);
+
+    // zoom towards the cursor, keeping the point under it in place, unless it's
+    // over the UI, where the wheel scrolls the UI and the keys zoom on the center
+    let mut new_scale = map_res.scale;
+    if !pointer_over_ui.0 {
+        let (_, wheel_y) = mouse_wheel();
+        if wheel_y > 0.0 {
+            new_scale /= WHEEL_ZOOM_FACTOR;
+        } else if wheel_y < 0.0 {
+            new_scale *= WHEEL_ZOOM_FACTOR;
+        }
+    }
+
+    // the scale is how much the map shows, so zooming in shrinks it
+    let key_zoom = KEY_ZOOM_FACTOR.powf(get_frame_time());
+    if action_map.is_down(Action::MapZoomIn) {
+        new_scale /= key_zoom;
+    }
+    if action_map.is_down(Action::MapZoomOut) {
+        new_scale *= key_zoom;
     }
 
-    if is_key_down(KeyCode::Down) {
-        map_res.scale = (map_res.scale - 0.25).max(0.5);
+    let new_scale = new_scale.clamp(MIN_MAP_SCALE, MAX_MAP_SCALE);
+    let center = focus_pos + map_res.position;
+    let anchor = if pointer_over_ui.0 {
+        center
+    } else {
+        mouse_world_pos
+    };
+    let new_center = anchor - (anchor - center) * (new_scale / map_res.scale);
+    map_res.position = new_center - focus_pos;
+    map_res.scale = new_scale;
+
+    // clicks and drags on the UI are meant for it, not the map
+    if pointer_over_ui.0 {
+        return;
+    }
+
+    if is_mouse_button_down(MouseButton::Left) {
+        map_res.position -= mouse_world_pos - camera.screen_to_world(last_mouse_pos);
+    }
+
+    if is_mouse_button_pressed(MouseButton::Left) {
+        let now = get_time();
+        if now - map_res.last_click_time < DOUBLE_CLICK_TIME {
+            let rocket_pos = kinematics_query.get(rocket_entity.0).unwrap().pos;
+            let tolerance = map_res.view_height() * CLICK_TOLERANCE;
+
+            let clicked_body = planet_query
+                .iter()
+                .find(|(_, planet, kinematics)| {
+                    (mouse_world_pos - kinematics.pos).length() < planet.radius + tolerance
+                })
+                .map(|(entity, _, _)| entity);
+
+            let new_focus = if (mouse_world_pos - rocket_pos).length() < tolerance {
+                Some(MapFocus::Rocket)
+            } else {
+                clicked_body.map(MapFocus::Body)
+            };
+
+            if let Some(focus) = new_focus {
+                map_res.focus = focus;
+                map_res.position = Vec2::new(0.0, 0.0);
+            }
+        }
+        map_res.last_click_time = now;
     }
 }
@@ -1,15 +1,114 @@
+use crate::actions::{Action, ActionMap};
 use crate::rocket::Rocket;
 use bevy_ecs::prelude::*;
 use egui_macroquad::macroquad::prelude::Vec2;
 
 use crate::planet::CelestialBody;
-use crate::rocket::RocketCrashed;
+use crate::rocket::{Altitude, FlightOutcome, Stages};
 use crate::trajectory::Trajectory;
 
+pub mod gforce;
+pub mod heat;
 pub mod offset;
+pub mod orbit;
+pub mod step;
+pub mod warp;
 
 pub struct DT(pub f32);
+
+/// how quickly the rocket's control system cancels out spin, per second
+const ANGULAR_DAMPING: f32 = 1.0;
+const MAX_ANGULAR_VEL: f32 = 10.0;
+
+/// fastest the rocket can touch the ground without crashing
+const SAFE_LANDING_SPEED: f32 = 0.01;
+/// steepest ground, in radians, the rocket can land on
+const MAX_LANDING_SLOPE: f32 = 0.25;
+/// furthest the rocket can lean away from the ground's normal, in radians, when
+/// touching down
+const MAX_LANDING_TILT: f32 = 0.35;
+/// how many ticks of gravity's worth of descent speed still counts as resting on
+/// the ground rather than touching down
+const RESTING_SPEED_FACTOR: f32 = 2.0;
+/// how far above the ground the rocket can be and still count as resting on it
+const RESTING_HEIGHT: f32 = 0.01;
 pub struct Steps(pub usize);
+
+/// Pausing and stepping through ticks one at a time, for debugging physics
+pub struct SimControl {
+    pub paused: bool,
+    /// ticks to run on the next frame while paused
+    pub pending_ticks: usize,
+    /// how many ticks `Action::StepTicks` runs
+    pub step_size: usize,
+}
+
+impl Default for SimControl {
+    fn default() -> Self {
+        SimControl {
+            paused: false,
+            pending_ticks: 0,
+            step_size: 10,
+        }
+    }
+}
+
+impl SimControl {
+    /// Pauses and queues `ticks` ticks to run
+    pub fn step(&mut self, ticks: usize) {
+        self.paused = true;
+        self.pending_ticks += ticks;
+    }
+}
+
+pub fn sim_control_input_sys(mut sim_control: ResMut<SimControl>, action_map: Res<ActionMap>) {
+    if action_map.is_pressed(Action::Pause) {
+        sim_control.paused = !sim_control.paused;
+    }
+
+    if action_map.is_pressed(Action::StepTick) {
+        sim_control.step(1);
+    }
+
+    if action_map.is_pressed(Action::StepTicks) {
+        let step_size = sim_control.step_size;
+        sim_control.step(step_size);
+    }
+}
+
+/// Simulated time, advanced by every physics tick, warped or not
+#[derive(Default)]
+pub struct SimTime {
+    /// seconds since the universe started, which everything time-dependent goes by
+    pub universal: f64,
+    /// universal time the engines were first fired at, `None` while on the pad
+    pub launch: Option<f64>,
+}
+
+impl SimTime {
+    /// zero until launch
+    pub fn mission_elapsed(&self) -> f64 {
+        self.launch.map_or(0.0, |launch| self.universal - launch)
+    }
+}
+
+pub fn sim_time_sys(
+    mut sim_time: ResMut<SimTime>,
+    rocket_query: Query<&Rocket, Without<Trajectory>>,
+    dt: Res<DT>,
+) {
+    if sim_time.launch.is_none() {
+        let launched = rocket_query
+            .iter()
+            .any(|rocket| rocket.thrust > 0.0 && rocket.current_fuel_mass > 0.0);
+        if launched {
+            sim_time.launch = Some(sim_time.universal);
+        }
+    }
+
+    sim_time.universal += dt.0 as f64;
+}
+
 pub struct Mass(pub f32);
 
 #[derive(Default, Copy, Clone)]
@@ -37,6 +136,23 @@ macro_rules! generate_integration_systems {
 generate_integration_systems!(Without, integration_sys);
 generate_integration_systems!(With, trajectory_integration_sys);
 
+macro_rules! generate_rotation_systems {
+    ($filter:ident, $name:ident) => {
+        pub fn $name(mut query: Query<&mut Rocket, $filter<Trajectory>>, dt: Res<DT>) {
+            for mut rocket in query.iter_mut() {
+                let dt = dt.0;
+                let angular_vel = rocket.angular_vel * (1.0 - ANGULAR_DAMPING * dt).max(0.0);
+
+                rocket.angular_vel = angular_vel.clamp(-MAX_ANGULAR_VEL, MAX_ANGULAR_VEL);
+                rocket.angle += rocket.angular_vel * dt;
+            }
+        }
+    };
+}
+
+generate_rotation_systems!(Without, rotation_sys);
+generate_rotation_systems!(With, trajectory_rotation_sys);
+
 // TODO: Rocket accel/gravity wrong direction
 macro_rules! generate_rocket_thrust_systems {
     ($filter:ident, $name:ident) => {
@@ -50,8 +166,7 @@ macro_rules! generate_rocket_thrust_systems {
             for (mut kinematics, mut rocket) in rockets {
                 let mass = rocket.total_mass();
                 let fuel_burned = rocket.fuel_burn_rate * dt.0 * rocket.thrust;
-                let thrust_force = fuel_burned * rocket.fuel_thrust_factor;
-                let thrust_accel = thrust_force / mass * crate::THRUST_MULTIPLIER;
+                let thrust_accel = rocket.max_thrust() * rocket.thrust / mass;
                 kinematics.acc += thrust_accel * Vec2::new(rocket.angle.sin(), rocket.angle.cos());
 
                 rocket.current_fuel_mass -= fuel_burned;
@@ -69,7 +184,7 @@ macro_rules! generate_planet_interaction_systems {
         pub fn $name(
             mut query_set: QuerySet<(
                 Query<(&Kinematics, &Rocket), $filter<Trajectory>>,
-                Query<(&mut Kinematics, &Rocket), $filter<Trajectory>>,
+                Query<(&mut Kinematics, &mut Rocket), $filter<Trajectory>>,
                 Query<(&CelestialBody, &Kinematics)>,
             )>,
             dt: Res<DT>,
@@ -80,32 +195,48 @@ macro_rules! generate_planet_interaction_systems {
             let planet_query = query_set.q2();
 
             let mut rocket_accels: Vec<Vec2> = vec![];
-            let mut rocket_dampings: Vec<f32> = vec![];
+            let mut rocket_drags: Vec<Vec2> = vec![];
+            let mut rocket_torques: Vec<f32> = vec![];
 
             for planet_info @ (planet, planet_kinematics) in planet_query.iter() {
                 for (i, rocket_info @ (rocket_kinematics, _)) in
                     rocket_immut_query.iter().enumerate()
                 {
                     rocket_accels.push(Vec2::new(0.0, 0.0));
-                    rocket_dampings.push(1.0);
+                    rocket_drags.push(Vec2::new(0.0, 0.0));
+                    rocket_torques.push(0.0);
                     let r = rocket_kinematics.pos - planet_kinematics.pos;
                     if r.length() > planet.radius {
-                        let (accel, damping) =
+                        let (accel, mut drag, torque) =
                             calculate_planet_interaction(rocket_info, planet_info);
+
+                        // aerodynamic forces can't do more than stop the rocket relative to the air
+                        let air_speed = (rocket_kinematics.vel
+                            - planet.air_velocity_at(planet_kinematics, rocket_kinematics.pos))
+                        .length();
+                        let max_drag = air_speed / dt;
+                        if drag.length() > max_drag {
+                            drag *= max_drag / drag.length();
+                        }
+
                         rocket_accels[i] += accel;
-                        rocket_dampings[i] *= damping;
+                        rocket_drags[i] += drag;
+                        rocket_torques[i] += torque;
                     }
                 }
             }
 
             let rocket_mut_query = query_set.q1_mut();
-            for (((mut rocket_kinematics, _), g_accel), atm_damping) in rocket_mut_query
-                .iter_mut()
-                .zip(rocket_accels.iter())
-                .zip(rocket_dampings.iter())
+            for ((((mut rocket_kinematics, mut rocket), g_accel), drag_accel), torque) in
+                rocket_mut_query
+                    .iter_mut()
+                    .zip(rocket_accels.iter())
+                    .zip(rocket_drags.iter())
+                    .zip(rocket_torques.iter())
             {
                 rocket_kinematics.acc -= *g_accel;
-                rocket_kinematics.vel *= (*atm_damping).powf(dt);
+                rocket_kinematics.acc += *drag_accel;
+                rocket.angular_vel += torque / rocket.moment_of_inertia() * dt;
             }
         }
     };
@@ -115,15 +246,83 @@ generate_planet_interaction_systems!(Without, rocket_planet_interaction_sys);
 generate_planet_interaction_systems!(With, trajectory_planet_interaction_sys);
 
 pub fn rocket_crash_sys(
-    rocket_query: Query<&Kinematics, (With<Rocket>, Without<Trajectory>)>,
-    planet_query: Query<(&Kinematics, &CelestialBody)>,
-    mut rocket_crashed: ResMut<RocketCrashed>,
+    mut rocket_query: Query<(&mut Kinematics, &mut Rocket), Without<Trajectory>>,
+    planet_query: Query<(&Kinematics, &CelestialBody), Without<Rocket>>,
+    mut flight_outcome: ResMut<FlightOutcome>,
+    dt: Res<DT>,
 ) {
-    for rocket_kinematics in rocket_query.iter() {
+    for (mut rocket_kinematics, mut rocket) in rocket_query.iter_mut() {
         for (planet_kinematics, planet) in planet_query.iter() {
-            if (rocket_kinematics.pos - planet_kinematics.pos).length() < planet.radius {
-                rocket_crashed.0 = true;
-                break;
+            let pos = rocket_kinematics.pos;
+            let r = pos - planet_kinematics.pos;
+            let surface_radius = planet.surface_radius_at(planet_kinematics, pos);
+            if r.length() >= surface_radius || flight_outcome.is_over() {
+                continue;
+            }
+
+            let ground_vel = planet.air_velocity_at(planet_kinematics, pos);
+            let impact_speed = (rocket_kinematics.vel - ground_vel).length();
+
+            let normal = planet.surface_normal_at(planet_kinematics, pos);
+            let heading = Vec2::new(rocket.angle.sin(), rocket.angle.cos());
+            let slope = normal.dot(r.normalize()).min(1.0).acos();
+            let tilt = heading.dot(normal).min(1.0).acos();
+
+            // a rocket resting on the ground only sinks in by a tick of gravity, and can
+            // be turned there without toppling over
+            let descent_speed = -(rocket_kinematics.vel - ground_vel).dot(r.normalize());
+            let resting_speed = planet.gravity_at(r.length()) * dt.0 * RESTING_SPEED_FACTOR;
+            let touching_down = descent_speed > resting_speed;
+
+            if impact_speed < SAFE_LANDING_SPEED
+                && slope < MAX_LANDING_SLOPE
+                && (!touching_down || tilt < MAX_LANDING_TILT)
+            {
+                // rest on the ground
+                rocket_kinematics.pos = planet_kinematics.pos + r.normalize() * surface_radius;
+                rocket_kinematics.vel = ground_vel;
+                rocket.angular_vel = 0.0;
+            } else {
+                *flight_outcome = FlightOutcome::Crashed { impact_speed };
+            }
+        }
+    }
+}
+
+/// Ends the flight once the rocket is out of fuel and stages and can't go anywhere,
+/// either sitting on the ground or in an orbit that never comes down. Anything
+/// falling back is left to land or crash.
+pub fn rocket_stranded_sys(
+    rocket_query: Query<(&Kinematics, &Rocket, &Stages, &Altitude), Without<Trajectory>>,
+    planet_query: Query<(&Kinematics, &CelestialBody), Without<Rocket>>,
+    mut flight_outcome: ResMut<FlightOutcome>,
+) {
+    if flight_outcome.is_over() {
+        return;
+    }
+
+    for (rocket_kinematics, rocket, stages, altitude) in rocket_query.iter() {
+        if rocket.current_fuel_mass > 0.0 || !stages.0.is_empty() {
+            continue;
+        }
+
+        if let Ok((planet_kinematics, planet)) = planet_query.get(altitude.closest_planet) {
+            let pos = rocket_kinematics.pos;
+            let ground_speed =
+                (rocket_kinematics.vel - planet.air_velocity_at(planet_kinematics, pos)).length();
+            let resting = altitude.height < RESTING_HEIGHT && ground_speed < SAFE_LANDING_SPEED;
+
+            let orbit = orbit::Orbit::from_state(
+                pos - planet_kinematics.pos,
+                rocket_kinematics.vel - planet_kinematics.vel,
+                crate::GRAVITY * planet.mass,
+            );
+            let surface_radius = planet.radius + planet.terrain.max_height();
+            let stuck_in_orbit = orbit.apoapsis.is_some()
+                && orbit.periapsis > planet.atmosphere_radius.max(surface_radius);
+
+            if resting || stuck_in_orbit {
+                *flight_outcome = FlightOutcome::Stranded;
             }
         }
     }
@@ -142,29 +341,61 @@ macro_rules! generate_reset_accel_systems {
 generate_reset_accel_systems!(Without, reset_accel_sys);
 generate_reset_accel_systems!(With, trajectory_reset_accel_sys);
 
+pub fn planet_rotation_sys(mut query: Query<&mut CelestialBody>, sim_time: Res<SimTime>) {
+    for mut planet in query.iter_mut() {
+        planet.rotation = planet.rotation_at(sim_time.universal);
+    }
+}
+
+/// Returns the gravitational acceleration, pointing away from the planet, the
+/// aerodynamic (drag and lift) acceleration, and the aerodynamic torque.
 pub fn calculate_planet_interaction(
     (rocket_kinematics, rocket): (&Kinematics, &Rocket),
     (planet, planet_kinematics): (&CelestialBody, &Kinematics),
-) -> (Vec2, f32) {
+) -> (Vec2, Vec2, f32) {
     use crate::GRAVITY as G;
-    let damping_eqn = |x: f32| 0.5 + x.sqrt() / 2.0;
 
     let r = rocket_kinematics.pos - planet_kinematics.pos;
     assert!(r.length() > planet.radius);
 
-    let _m1 = rocket.total_mass();
+    let m1 = rocket.total_mass();
     let m2 = planet.mass;
 
     let a_g = G * m2 / r.length_squared();
-
-    let atmosphere_proportion = r.length() / planet.atmosphere_radius;
     let g_accel = a_g * r.normalize();
 
-    let damping = if atmosphere_proportion < 1.0 {
-        damping_eqn(atmosphere_proportion)
+    // the atmosphere rotates along with the planet
+    let air_vel =
+        rocket_kinematics.vel - planet.air_velocity_at(planet_kinematics, rocket_kinematics.pos);
+    let density = planet.atmosphere_density(r.length() - planet.radius);
+    let (aero_accel, aero_torque) = if density > 0.0 && air_vel.length_squared() > 0.0 {
+        let air_dir = air_vel.normalize();
+        let heading = Vec2::new(rocket.angle.sin(), rocket.angle.cos());
+
+        // positive when the nose is counter-clockwise of the airflow
+        let angle_of_attack =
+            (air_dir.x * heading.y - air_dir.y * heading.x).atan2(air_dir.dot(heading));
+        let dynamic_pressure = 0.5 * density * air_vel.length_squared();
+
+        // flying sideways exposes more of the body to the airflow
+        let drag_coefficient =
+            rocket.drag_coefficient * (1.0 + 4.0 * angle_of_attack.sin().powi(2));
+        let lift_coefficient = rocket.lift_coefficient * (2.0 * angle_of_attack).sin();
+
+        let drag = -air_dir * dynamic_pressure * drag_coefficient * rocket.cross_section;
+        let lift = Vec2::new(-air_dir.y, air_dir.x)
+            * dynamic_pressure
+            * lift_coefficient
+            * rocket.cross_section;
+
+        // a stable rocket turns its nose back into the airflow, an unstable one flips
+        let torque =
+            rocket.stability * dynamic_pressure * rocket.cross_section * angle_of_attack.sin();
+
+        ((drag + lift) / m1, torque)
     } else {
-        1.0
+        (Vec2::new(0.0, 0.0), 0.0)
     };
 
-    (g_accel, damping)
+    (g_accel, aero_accel, aero_torque)
 }
@@ -0,0 +1,79 @@
+use bevy_ecs::prelude::*;
+use egui_macroquad::macroquad::prelude::Vec2;
+
+use crate::physics::{Kinematics, DT};
+use crate::planet::CelestialBody;
+use crate::rocket::{Altitude, FlightOutcome, Rocket};
+use crate::trajectory::Trajectory;
+
+/// surface gravity of Earth, the unit the g-meter reads in
+pub const STANDARD_GRAVITY: f32 = crate::GRAVITY * 600_000.0 / (6000.0 * 6000.0);
+
+/// how long spikes in acceleration are smoothed over, in seconds
+const SMOOTHING_TIME: f32 = 0.1;
+
+pub struct GForce {
+    /// sensed acceleration in g, smoothed over `SMOOTHING_TIME`
+    pub current: f32,
+    /// structural limit in g
+    pub max: f32,
+}
+
+impl Default for GForce {
+    fn default() -> Self {
+        GForce {
+            current: 0.0,
+            max: 50.0,
+        }
+    }
+}
+
+/// Needs to run after all forces have been added to `Kinematics::acc` but before
+/// they're reset
+#[allow(clippy::type_complexity)]
+pub fn rocket_g_force_sys(
+    mut rocket_query: Query<
+        (&mut GForce, &Kinematics, &Altitude),
+        (With<Rocket>, Without<Trajectory>),
+    >,
+    planet_query: Query<(&CelestialBody, &Kinematics)>,
+    mut flight_outcome: ResMut<FlightOutcome>,
+    dt: Res<DT>,
+) {
+    for (mut g_force, rocket_kinematics, altitude) in rocket_query.iter_mut() {
+        // gravity pulls on every part of the rocket equally, so it isn't felt
+        let gravity_accel = planet_query
+            .iter()
+            .map(|(planet, planet_kinematics)| {
+                let r = planet_kinematics.pos - rocket_kinematics.pos;
+                planet.gravity_at(r.length()) * r.normalize()
+            })
+            .fold(Vec2::new(0.0, 0.0), |acc, accel| acc + accel);
+
+        // the ground pushes back against whatever presses the rocket into it, which is
+        // felt even though it never shows up in `acc`
+        let mut support_accel = Vec2::new(0.0, 0.0);
+        if let Ok((planet, planet_kinematics)) = planet_query.get(altitude.closest_planet) {
+            let pos = rocket_kinematics.pos;
+            let up = (pos - planet_kinematics.pos).normalize();
+            let ground_speed =
+                (rocket_kinematics.vel - planet.air_velocity_at(planet_kinematics, pos)).length();
+            let resting =
+                altitude.height < super::RESTING_HEIGHT && ground_speed < super::SAFE_LANDING_SPEED;
+            if resting {
+                support_accel = (-rocket_kinematics.acc.dot(up)).max(0.0) * up;
+            }
+        }
+
+        let sensed =
+            (rocket_kinematics.acc - gravity_accel + support_accel).length() / STANDARD_GRAVITY;
+        let smoothing = (dt.0 / SMOOTHING_TIME).min(1.0);
+        g_force.current += (sensed - g_force.current) * smoothing;
+
+        if g_force.current > g_force.max && !flight_outcome.is_over() {
+            *flight_outcome = FlightOutcome::OverG {
+                g_force: g_force.current,
+            };
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use bevy_ecs::prelude::*;
+
+use crate::physics::{Kinematics, DT};
+use crate::planet::CelestialBody;
+use crate::rocket::{Altitude, FlightOutcome, Rocket};
+use crate::trajectory::Trajectory;
+
+/// heat gained per unit of sqrt(density) * speed^3
+const HEATING_COEFFICIENT: f32 = 0.001;
+/// fraction of the temperature radiated away per second
+const COOLING_COEFFICIENT: f32 = 0.2;
+
+pub struct Heat {
+    pub temperature: f32,
+    pub max_temperature: f32,
+    /// heat gained from the atmosphere during the last tick, per second
+    pub heating_rate: f32,
+}
+
+impl Default for Heat {
+    fn default() -> Self {
+        Heat {
+            temperature: 0.0,
+            max_temperature: 1000.0,
+            heating_rate: 0.0,
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn rocket_heating_sys(
+    mut rocket_query: Query<
+        (&mut Heat, &Kinematics, &Altitude),
+        (With<Rocket>, Without<Trajectory>),
+    >,
+    planet_query: Query<(&CelestialBody, &Kinematics)>,
+    mut flight_outcome: ResMut<FlightOutcome>,
+    dt: Res<DT>,
+) {
+    let dt = dt.0;
+
+    for (mut heat, rocket_kinematics, altitude) in rocket_query.iter_mut() {
+        heat.heating_rate = match planet_query.get(altitude.closest_planet) {
+            Ok((planet, planet_kinematics)) => {
+                let density = planet.atmosphere_density(altitude.height);
+                let air_speed = (rocket_kinematics.vel
+                    - planet.air_velocity_at(planet_kinematics, rocket_kinematics.pos))
+                .length();
+                HEATING_COEFFICIENT * density.sqrt() * air_speed.powi(3)
+            }
+            Err(_) => 0.0,
+        };
+
+        let cooling_rate = COOLING_COEFFICIENT * heat.temperature;
+        heat.temperature += (heat.heating_rate - cooling_rate) * dt;
+        heat.temperature = heat.temperature.max(0.0);
+
+        if heat.temperature > heat.max_temperature && !flight_outcome.is_over() {
+            *flight_outcome = FlightOutcome::Overheated {
+                temperature: heat.temperature,
+            };
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use egui_macroquad::macroquad::prelude::Vec2;
+
+/// Two-body orbital elements of a rocket around a single body
+#[derive(Copy, Clone)]
+pub struct Orbit {
+    pub semi_major_axis: f32,
+    pub eccentricity: f32,
+    /// closest distance from the body's center
+    pub periapsis: f32,
+    /// furthest distance from the body's center, `None` on an escape trajectory
+    pub apoapsis: Option<f32>,
+    /// unit vector from the body's center towards the periapsis, zero for circular orbits
+    pub periapsis_direction: Vec2,
+}
+
+impl Orbit {
+    /// `pos` and `vel` are relative to the body, `mu` is `GRAVITY * mass`
+    pub fn from_state(pos: Vec2, vel: Vec2, mu: f32) -> Self {
+        let r = pos.length();
+        let energy = vel.length_squared() / 2.0 - mu / r;
+        let angular_momentum = pos.x * vel.y - pos.y * vel.x;
+
+        let eccentricity = (1.0 + 2.0 * energy * angular_momentum.powi(2) / mu.powi(2))
+            .max(0.0)
+            .sqrt();
+        let semi_major_axis = -mu / (2.0 * energy);
+        let periapsis = angular_momentum.powi(2) / (mu * (1.0 + eccentricity));
+        let apoapsis = if eccentricity < 1.0 {
+            Some(semi_major_axis * (1.0 + eccentricity))
+        } else {
+            None
+        };
+
+        let eccentricity_vector = ((vel.length_squared() - mu / r) * pos - pos.dot(vel) * vel) / mu;
+        let periapsis_direction = if eccentricity_vector.length_squared() > 1e-10 {
+            eccentricity_vector.normalize()
+        } else {
+            Vec2::new(0.0, 0.0)
+        };
+
+        Orbit {
+            semi_major_axis,
+            eccentricity,
+            periapsis,
+            apoapsis,
+            periapsis_direction,
+        }
+    }
+}
+
+/// Moves a body along its conic for `dt` seconds, returning its new position and
+/// velocity. `pos` and `vel` are relative to the body it orbits, `mu` is `GRAVITY * mass`.
+///
+/// Uses the universal variable formulation so it works for both bound and escape
+/// trajectories, in `f64` since warps can cover many orbits.
+pub fn propagate(pos: Vec2, vel: Vec2, mu: f32, dt: f32) -> (Vec2, Vec2) {
+    let (x0, y0) = (pos.x as f64, pos.y as f64);
+    let (vx0, vy0) = (vel.x as f64, vel.y as f64);
+    let mu = mu as f64;
+    let sqrt_mu = mu.sqrt();
+
+    let r0 = (x0 * x0 + y0 * y0).sqrt();
+    let v0_squared = vx0 * vx0 + vy0 * vy0;
+    let radial_vel = (x0 * vx0 + y0 * vy0) / r0;
+    // reciprocal of the semi-major axis, negative for escape trajectories
+    let alpha = 2.0 / r0 - v0_squared / mu;
+
+    let mut dt = dt as f64;
+    if alpha > 1e-12 {
+        // whole orbits change nothing, and would only slow down the solver
+        let period = std::f64::consts::TAU / (mu * alpha.powi(3)).sqrt();
+        dt %= period;
+    }
+
+    let mut chi = sqrt_mu * alpha.abs() * dt;
+    for _ in 0..50 {
+        let z = alpha * chi * chi;
+        let (c, s) = (stumpff_c(z), stumpff_s(z));
+        let f = r0 * radial_vel / sqrt_mu * chi * chi * c
+            + (1.0 - alpha * r0) * chi.powi(3) * s
+            + r0 * chi
+            - sqrt_mu * dt;
+        let df = r0 * radial_vel / sqrt_mu * chi * (1.0 - z * s)
+            + (1.0 - alpha * r0) * chi * chi * c
+            + r0;
+
+        let step = f / df;
+        chi -= step;
+        if step.abs() < 1e-9 {
+            break;
+        }
+    }
+
+    let z = alpha * chi * chi;
+    let (c, s) = (stumpff_c(z), stumpff_s(z));
+    let f = 1.0 - chi * chi / r0 * c;
+    let g = dt - chi.powi(3) / sqrt_mu * s;
+    let (x, y) = (f * x0 + g * vx0, f * y0 + g * vy0);
+
+    let r = (x * x + y * y).sqrt();
+    let f_dot = sqrt_mu / (r * r0) * (z * chi * s - chi);
+    let g_dot = 1.0 - chi * chi / r * c;
+    let (vx, vy) = (f_dot * x0 + g_dot * vx0, f_dot * y0 + g_dot * vy0);
+
+    (
+        Vec2::new(x as f32, y as f32),
+        Vec2::new(vx as f32, vy as f32),
+    )
+}
+
+fn stumpff_c(z: f64) -> f64 {
+    if z > 1e-9 {
+        (1.0 - z.sqrt().cos()) / z
+    } else if z < -1e-9 {
+        ((-z).sqrt().cosh() - 1.0) / -z
+    } else {
+        0.5
+    }
+}
+
+fn stumpff_s(z: f64) -> f64 {
+    if z > 1e-9 {
+        let sqrt_z = z.sqrt();
+        (sqrt_z - sqrt_z.sin()) / sqrt_z.powi(3)
+    } else if z < -1e-9 {
+        let sqrt_z = (-z).sqrt();
+        (sqrt_z.sinh() - sqrt_z) / sqrt_z.powi(3)
+    } else {
+        1.0 / 6.0
+    }
+}
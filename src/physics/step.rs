@@ -0,0 +1,120 @@
+use bevy_ecs::prelude::*;
+use egui_macroquad::macroquad::prelude::Vec2;
+
+use crate::physics::{Kinematics, DT};
+use crate::planet::CelestialBody;
+use crate::rocket::Rocket;
+use crate::trajectory::Trajectory;
+
+/// the tick length everything is tuned for, and the shortest adaptive step
+pub const BASE_DT: f32 = 1.0 / 60.0;
+/// longest a single tick can be, in seconds
+const MAX_DT: f32 = 10.0;
+
+/// fraction of the local orbital timescale, `sqrt(r^3 / mu)`, a tick can span
+const GRADIENT_FACTOR: f32 = 0.005;
+/// largest velocity error, in world units per second, allowed in a single tick
+const ERROR_TOLERANCE: f32 = 1e-4;
+/// fraction of the time until the rocket could reach a body's highest terrain
+/// that a tick can span
+const APPROACH_FACTOR: f32 = 0.1;
+/// how far above a body's atmosphere and terrain longer ticks are allowed
+const SAFETY_ALTITUDE: f32 = 10.0;
+/// how much a tick can grow or shrink compared to the last one
+const MAX_GROWTH: f32 = 2.0;
+const MAX_SHRINK: f32 = 0.5;
+
+/// wall clock time, in seconds, the physics can take per frame before the rest
+/// of the frame's simulated time is dropped
+pub const FRAME_BUDGET: f64 = 0.012;
+
+/// Picks how long the next physics tick is
+///
+/// Ticks stay at `BASE_DT` while the engines are on, while there's input, or
+/// while the rocket is in an atmosphere or near terrain. Otherwise they grow while
+/// the gravity around the rocket changes slowly and shrink where it changes
+/// quickly, like at periapsis. They're always a whole number of `BASE_DT`s, and
+/// are cut short to fit the time left in a frame, so a frame never runs ahead.
+pub struct AdaptiveStep {
+    /// longest the next tick can be
+    pub dt: f32,
+    /// the rocket's acceleration at the start of the last tick and that tick's
+    /// length, to estimate its error
+    last_acc: Option<(Vec2, f32)>,
+}
+
+impl Default for AdaptiveStep {
+    fn default() -> Self {
+        AdaptiveStep {
+            dt: BASE_DT,
+            last_acc: None,
+        }
+    }
+}
+
+impl AdaptiveStep {
+    /// Goes back to `BASE_DT` ticks, growing them again from scratch
+    pub fn reset(&mut self) {
+        self.dt = BASE_DT;
+        self.last_acc = None;
+    }
+}
+
+/// Runs after every force on the rocket has been added for the tick, and sets the
+/// length of the next one
+pub fn adaptive_step_sys(
+    rocket_query: Query<(&Kinematics, &Rocket), Without<Trajectory>>,
+    planet_query: Query<(&CelestialBody, &Kinematics), Without<Rocket>>,
+    mut adaptive_step: ResMut<AdaptiveStep>,
+    dt: Res<DT>,
+) {
+    let (kinematics, rocket) = match rocket_query.iter().next() {
+        Some(rocket) => rocket,
+        None => return,
+    };
+
+    if rocket.thrust > 0.0 && rocket.current_fuel_mass > 0.0 {
+        adaptive_step.reset();
+        return;
+    }
+
+    let mut max_dt = MAX_DT;
+    for (planet, planet_kinematics) in planet_query.iter() {
+        let r = kinematics.pos - planet_kinematics.pos;
+        let distance = r.length();
+        let safe_radius = planet
+            .atmosphere_radius
+            .max(planet.radius + planet.terrain.max_height())
+            + SAFETY_ALTITUDE;
+
+        if distance < safe_radius {
+            adaptive_step.reset();
+            return;
+        }
+
+        let mu = crate::GRAVITY * planet.mass;
+        max_dt = max_dt.min(GRADIENT_FACTOR * (distance.powi(3) / mu).sqrt());
+
+        // can't cover more than a fraction of the gap to the body in one tick
+        let closing_speed = -(kinematics.vel - planet_kinematics.vel).dot(r) / distance;
+        if closing_speed > 0.0 {
+            let gap = distance - (planet.radius + planet.terrain.max_height());
+            max_dt = max_dt.min(APPROACH_FACTOR * gap / closing_speed);
+        }
+    }
+
+    // the integrator holds the acceleration fixed over a tick, so its velocity is
+    // off by about half of how much the acceleration changed over it
+    let mut next_dt = dt.0 * MAX_GROWTH;
+    if let Some((last_acc, last_dt)) = adaptive_step.last_acc {
+        let error = 0.5 * (kinematics.acc - last_acc).length() * last_dt;
+        if error > 0.0 {
+            let scale = (0.9 * (ERROR_TOLERANCE / error).sqrt()).clamp(MAX_SHRINK, MAX_GROWTH);
+            next_dt = last_dt * scale;
+        }
+    }
+
+    let base_ticks = (next_dt.min(max_dt) / BASE_DT).floor().max(1.0);
+    adaptive_step.dt = base_ticks * BASE_DT;
+    adaptive_step.last_acc = Some((kinematics.acc, dt.0));
+}
@@ -0,0 +1,166 @@
+use bevy_ecs::prelude::*;
+
+use crate::actions::{Action, ActionMap};
+use crate::physics::orbit;
+use crate::physics::{Kinematics, DT};
+use crate::planet::{CelestialBody, SpheresOfInfluence};
+use crate::replay::ReplayState;
+use crate::rocket::Rocket;
+use crate::trajectory::Trajectory;
+
+/// how many times faster than real time each warp level runs
+pub const WARP_FACTORS: [f32; 6] = [1.0, 10.0, 100.0, 1_000.0, 10_000.0, 100_000.0];
+
+/// longest frame, in real seconds, warp keeps pace with, so a stalled frame doesn't
+/// jump the rocket far along its orbit at once
+pub const MAX_WARP_FRAME_TIME: f32 = 0.1;
+
+/// how many pieces each frame's warp is split into, so drop-outs happen near the
+/// point that caused them
+pub const WARP_SUBSTEPS: usize = 20;
+
+/// how far above a body's atmosphere, or its highest terrain if it has none,
+/// warp stops, in world units
+const WARP_SAFETY_ALTITUDE: f32 = 50.0;
+
+/// how far around the body, roughly in radians, the rocket moves between checks
+const MAX_PIECE_ANGLE: f32 = 0.05;
+const MAX_PIECES: usize = 1000;
+
+/// On-rails time warp: while unpowered and clear of atmospheres the rocket is moved
+/// analytically along its conic instead of being simulated tick by tick
+#[derive(Default)]
+pub struct TimeWarp {
+    /// index into `WARP_FACTORS`, 0 when not warping
+    pub level: usize,
+    /// why warp last stopped or couldn't start, shown in the UI
+    pub status: Option<String>,
+}
+
+impl TimeWarp {
+    pub fn factor(&self) -> f32 {
+        WARP_FACTORS[self.level]
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.level > 0
+    }
+
+    pub fn stop(&mut self, reason: String) {
+        self.level = 0;
+        self.status = Some(reason);
+    }
+}
+
+pub fn warp_input_sys(
+    mut time_warp: ResMut<TimeWarp>,
+    action_map: Res<ActionMap>,
+    replay: Res<ReplayState>,
+) {
+    // the replay drives the warp while it's playing
+    if replay.is_playing() {
+        return;
+    }
+
+    if action_map.is_pressed(Action::WarpFaster) && time_warp.level + 1 < WARP_FACTORS.len() {
+        time_warp.level += 1;
+        time_warp.status = None;
+    }
+
+    if action_map.is_pressed(Action::WarpSlower) && time_warp.is_active() {
+        time_warp.level -= 1;
+    }
+}
+
+/// Moves the rocket along its conic around the body whose sphere of influence it's in,
+/// dropping out of warp when the engines are on, or when it gets close to a body or
+/// leaves its sphere of influence.
+///
+/// Runs in place of the physics systems, with `DT` set to the warped time. When warp
+/// stops partway, `DT` is cut down to the time that actually passed.
+pub fn rocket_warp_sys(
+    mut rocket_query: Query<(&mut Kinematics, &mut Rocket), Without<Trajectory>>,
+    planet_query: Query<(Entity, &CelestialBody, &Kinematics), Without<Rocket>>,
+    mut time_warp: ResMut<TimeWarp>,
+    mut dt: ResMut<DT>,
+) {
+    if !time_warp.is_active() {
+        return;
+    }
+
+    let spheres = SpheresOfInfluence::new(planet_query.iter());
+
+    for (mut kinematics, mut rocket) in rocket_query.iter_mut() {
+        if rocket.thrust > 0.0 && rocket.current_fuel_mass > 0.0 {
+            time_warp.stop("Can't warp with the engines running".to_string());
+            dt.0 = 0.0;
+            return;
+        }
+
+        let body = match spheres.dominant_body(kinematics.pos) {
+            Some(body) => body,
+            None => {
+                dt.0 = 0.0;
+                return;
+            }
+        };
+        let (_, planet, planet_kinematics) = planet_query.get(body).unwrap();
+        let safe_radius = planet
+            .atmosphere_radius
+            .max(planet.radius + planet.terrain.max_height())
+            + WARP_SAFETY_ALTITUDE;
+
+        if (kinematics.pos - planet_kinematics.pos).length() < safe_radius {
+            time_warp.stop(format!("Too close to {} to warp", planet.name));
+            dt.0 = 0.0;
+            return;
+        }
+
+        let mu = crate::GRAVITY * planet.mass;
+        let mut pos = kinematics.pos - planet_kinematics.pos;
+        let mut vel = kinematics.vel - planet_kinematics.vel;
+
+        // short enough pieces that the rocket can't skip past a body or boundary
+        let piece_time = MAX_PIECE_ANGLE * pos.length() / vel.length().max(1e-6);
+        let pieces = ((dt.0 / piece_time).ceil() as usize).clamp(1, MAX_PIECES);
+        let piece_dt = dt.0 / pieces as f32;
+        let mut warped_time = 0.0;
+
+        for _ in 0..pieces {
+            let (next_pos, next_vel) = orbit::propagate(pos, vel, mu, piece_dt);
+
+            if next_pos.length() < safe_radius {
+                // stop right at the edge, rather than somewhere inside it
+                let (mut low, mut high) = (0.0, piece_dt);
+                for _ in 0..20 {
+                    let mid = (low + high) / 2.0;
+                    if orbit::propagate(pos, vel, mu, mid).0.length() < safe_radius {
+                        high = mid;
+                    } else {
+                        low = mid;
+                    }
+                }
+                let (edge_pos, edge_vel) = orbit::propagate(pos, vel, mu, low);
+                pos = edge_pos;
+                vel = edge_vel;
+                warped_time += low;
+                time_warp.stop(format!("Approaching {}", planet.name));
+                break;
+            }
+
+            pos = next_pos;
+            vel = next_vel;
+            warped_time += piece_dt;
+
+            if spheres.dominant_body(planet_kinematics.pos + pos) != Some(body) {
+                time_warp.stop(format!("Leaving {}'s sphere of influence", planet.name));
+                break;
+            }
+        }
+
+        kinematics.pos = planet_kinematics.pos + pos;
+        kinematics.vel = planet_kinematics.vel + vel;
+        rocket.angular_vel = 0.0;
+        dt.0 = warped_time;
+    }
+}
@@ -1,44 +1,232 @@
+use crate::camera::{curve_segments, CameraRes};
 use crate::physics::Kinematics;
 use crate::rocket::{Altitude, RocketEntity};
+use crate::terrain::Terrain;
 use crate::texture::{TextureName, Textures};
 use bevy_ecs::prelude::*;
 use egui_macroquad::macroquad::prelude::*;
 
 pub struct CelestialBody {
+    pub name: String,
     pub radius: f32,
     pub mass: f32,
     pub atmosphere_radius: f32,
+    /// air density at the surface, zero for bodies without air
+    pub sea_level_density: f32,
+    /// altitude over which the air density falls by a factor of e
+    pub scale_height: f32,
     pub atmosphere_color: Color,
     pub texture: TextureName,
+    pub terrain: Terrain,
+    pub surface_color: Color,
+    /// seconds per counter-clockwise revolution
+    pub rotation_period: f32,
+    /// current rotation angle in radians, kept at `rotation_at` the universal time
+    pub rotation: f32,
+}
+
+impl CelestialBody {
+    /// gravitational acceleration at `distance` from the body's center
+    pub fn gravity_at(&self, distance: f32) -> f32 {
+        crate::GRAVITY * self.mass / (distance * distance)
+    }
+
+    pub fn surface_gravity(&self) -> f32 {
+        self.gravity_at(self.radius)
+    }
+
+    pub fn angular_vel(&self) -> f32 {
+        std::f32::consts::TAU / self.rotation_period
+    }
+
+    /// rotation angle at `universal_time`, bodies all start unrotated at time zero
+    pub fn rotation_at(&self, universal_time: f64) -> f32 {
+        let turns = (universal_time / self.rotation_period as f64).fract();
+        (turns * std::f64::consts::TAU) as f32
+    }
+
+    /// velocity of the ground or air at `pos`, including the body's own motion
+    pub fn air_velocity_at(&self, kinematics: &Kinematics, pos: Vec2) -> Vec2 {
+        let r = pos - kinematics.pos;
+        kinematics.vel + self.angular_vel() * Vec2::new(-r.y, r.x)
+    }
+
+    /// angle of `pos` around the body in the body's rotating frame, as a fraction of a turn
+    pub fn surface_turn(&self, kinematics: &Kinematics, pos: Vec2) -> f32 {
+        let r = pos - kinematics.pos;
+        (r.y.atan2(r.x) - self.rotation) / std::f32::consts::TAU
+    }
+
+    /// distance from the body's center to the ground below `pos`
+    pub fn surface_radius_at(&self, kinematics: &Kinematics, pos: Vec2) -> f32 {
+        self.radius + self.terrain.height_at(self.surface_turn(kinematics, pos))
+    }
+
+    /// the point on the ground at `angle` around the body, in world coordinates
+    pub fn surface_point(&self, kinematics: &Kinematics, angle: f32) -> Vec2 {
+        let direction = Vec2::new(angle.cos(), angle.sin());
+        let turn = (angle - self.rotation) / std::f32::consts::TAU;
+        kinematics.pos + direction * (self.radius + self.terrain.height_at(turn))
+    }
+
+    /// outward facing normal of the ground below `pos`
+    pub fn surface_normal_at(&self, kinematics: &Kinematics, pos: Vec2) -> Vec2 {
+        let r = pos - kinematics.pos;
+        let angle = r.y.atan2(r.x);
+        let delta = 0.05 / self.radius;
+
+        let tangent = self.surface_point(kinematics, angle + delta)
+            - self.surface_point(kinematics, angle - delta);
+        let normal = Vec2::new(tangent.y, -tangent.x).normalize();
+
+        if normal.dot(r) < 0.0 {
+            -normal
+        } else {
+            normal
+        }
+    }
+
+    pub fn has_atmosphere(&self) -> bool {
+        self.sea_level_density > 0.0
+    }
+
+    /// radius within which this body's gravity dominates over `primary`'s,
+    /// when the two are `distance` apart
+    pub fn sphere_of_influence(&self, distance: f32, primary: &CelestialBody) -> f32 {
+        distance * (self.mass / primary.mass).powf(0.4)
+    }
+
+    pub fn atmosphere_density(&self, altitude: f32) -> f32 {
+        if altitude < self.atmosphere_radius - self.radius {
+            self.sea_level_density * (-altitude.max(0.0) / self.scale_height).exp()
+        } else {
+            0.0
+        }
+    }
+}
+
+/// A body's sphere of influence
+pub struct SphereOfInfluence {
+    pub body: Entity,
+    pub center: Vec2,
+    pub radius: f32,
+}
+
+/// Where each body's gravity dominates. The most massive body is the primary and
+/// has no sphere, since it dominates everywhere outside the others.
+pub struct SpheresOfInfluence {
+    pub primary: Option<Entity>,
+    /// smallest first, so nested spheres are checked innermost first
+    pub spheres: Vec<SphereOfInfluence>,
+}
+
+impl SpheresOfInfluence {
+    pub fn new<'a>(
+        bodies: impl Iterator<Item = (Entity, &'a CelestialBody, &'a Kinematics)>,
+    ) -> Self {
+        let bodies = bodies.collect::<Vec<_>>();
+        let primary = bodies
+            .iter()
+            .max_by(|(_, a, _), (_, b, _)| a.mass.partial_cmp(&b.mass).unwrap());
+
+        let (primary_entity, primary_body, primary_kinematics) = match primary {
+            Some(primary) => *primary,
+            None => {
+                return SpheresOfInfluence {
+                    primary: None,
+                    spheres: Vec::new(),
+                }
+            }
+        };
+
+        let mut spheres = bodies
+            .iter()
+            .filter(|(entity, _, _)| *entity != primary_entity)
+            .map(|(body, planet, kinematics)| {
+                let distance = (kinematics.pos - primary_kinematics.pos).length();
+                SphereOfInfluence {
+                    body: *body,
+                    center: kinematics.pos,
+                    radius: planet.sphere_of_influence(distance, primary_body),
+                }
+            })
+            .collect::<Vec<_>>();
+        spheres.sort_by(|a, b| a.radius.partial_cmp(&b.radius).unwrap());
+
+        SpheresOfInfluence {
+            primary: Some(primary_entity),
+            spheres,
+        }
+    }
+
+    /// The body whose sphere of influence `point` is in
+    pub fn dominant_body(&self, point: Vec2) -> Option<Entity> {
+        self.spheres
+            .iter()
+            .find(|sphere| (point - sphere.center).length() < sphere.radius)
+            .map(|sphere| sphere.body)
+            .or(self.primary)
+    }
+}
+
+pub fn default_bodies() -> Vec<(CelestialBody, Kinematics)> {
+    vec![
+        (
+            CelestialBody {
+                name: "Earth".to_string(),
+                radius: 6000.0,
+                atmosphere_radius: 6700.0,
+                sea_level_density: 25_000.0,
+                scale_height: 120.0,
+                mass: 600_000.0,
+                atmosphere_color: SKYBLUE,
+                texture: TextureName::Earth,
+                terrain: Terrain::Procedural {
+                    seed: 1,
+                    amplitude: 3.0,
+                    features: 256,
+                    octaves: 10,
+                    persistence: 0.6,
+                },
+                surface_color: Color::new(0.2, 0.45, 0.2, 1.0),
+                rotation_period: 30_000.0,
+                rotation: 0.0,
+            },
+            Kinematics::default(),
+        ),
+        (
+            CelestialBody {
+                name: "Moon".to_string(),
+                radius: 1500.0,
+                atmosphere_radius: 1500.1,
+                sea_level_density: 0.0,
+                scale_height: 1.0,
+                mass: 7_000.0,
+                atmosphere_color: SKYBLUE,
+                texture: TextureName::Moon,
+                terrain: Terrain::Procedural {
+                    seed: 2,
+                    amplitude: 2.0,
+                    features: 64,
+                    octaves: 10,
+                    persistence: 0.65,
+                },
+                surface_color: GRAY,
+                rotation_period: 100_000.0,
+                rotation: 0.0,
+            },
+            Kinematics {
+                pos: Vec2::new(0.0, 380_000.0),
+                ..Kinematics::default()
+            },
+        ),
+    ]
 }
 
 pub fn add_planets(world: &mut World) {
-    // Earth
-    world
-        .spawn()
-        .insert(CelestialBody {
-            radius: 6000.0,
-            atmosphere_radius: 6700.0,
-            mass: 600_000.0,
-            atmosphere_color: SKYBLUE,
-            texture: TextureName::Earth,
-        })
-        .insert(Kinematics::default());
-
-    // Moon
-    world
-        .spawn()
-        .insert(CelestialBody {
-            radius: 1500.0,
-            atmosphere_radius: 1500.1,
-            mass: 7_000.0,
-            atmosphere_color: SKYBLUE,
-            texture: TextureName::Moon,
-        })
-        .insert(Kinematics {
-            pos: Vec2::new(0.0, 380_000.0),
-            ..Kinematics::default()
-        });
+    for (body, kinematics) in default_bodies() {
+        world.spawn().insert(body).insert(kinematics);
+    }
 }
 
 pub fn draw_atmosphere_sys(
@@ -69,14 +257,76 @@ fn draw_planet(planet: &CelestialBody, kinematics: &Kinematics, textures: &Textu
         WHITE,
         DrawTextureParams {
             dest_size: Some(Vec2::new(size, size)),
+            rotation: planet.rotation,
             flip_y: true,
             ..DrawTextureParams::default()
         },
     );
 }
 
-pub fn draw_planet_sys(query: Query<(&CelestialBody, &Kinematics)>, textures: Res<Textures>) {
+/// Draws the part of the surface the camera is looking at as a tessellated arc,
+/// with enough segments to show the terrain at the current zoom
+fn draw_surface_arc(
+    planet: &CelestialBody,
+    kinematics: &Kinematics,
+    camera_target: Vec2,
+    view_radius: f32,
+) {
+    let r = camera_target - kinematics.pos;
+    let center_angle = r.y.atan2(r.x);
+    let half_span = (view_radius / planet.radius).min(std::f32::consts::PI);
+    let segments = curve_segments(planet.radius * half_span * 2.0, view_radius);
+    let step = half_span * 2.0 / segments as f32;
+
+    // deep enough to fill the rest of the screen below the surface
+    let base_radius = (planet.radius - view_radius * 2.0).max(0.0);
+
+    let mut prev_angle = center_angle - half_span;
+    let mut prev_point = planet.surface_point(kinematics, prev_angle);
+    for i in 1..=segments {
+        let angle = center_angle - half_span + step * i as f32;
+        let point = planet.surface_point(kinematics, angle);
+
+        let prev_base =
+            kinematics.pos + Vec2::new(prev_angle.cos(), prev_angle.sin()) * base_radius;
+        let base = kinematics.pos + Vec2::new(angle.cos(), angle.sin()) * base_radius;
+        draw_triangle(prev_point, point, base, planet.surface_color);
+        draw_triangle(prev_point, base, prev_base, planet.surface_color);
+        draw_line(
+            prev_point.x,
+            prev_point.y,
+            point.x,
+            point.y,
+            view_radius / 400.0,
+            DARKGRAY,
+        );
+
+        prev_angle = angle;
+        prev_point = point;
+    }
+}
+
+pub fn draw_planet_sys(
+    query: Query<(&CelestialBody, &Kinematics)>,
+    textures: Res<Textures>,
+    camera_res: Res<CameraRes>,
+) {
+    // below this ratio of view size to planet radius, the texture's pixels get too big
+    const SURFACE_LOD_RATIO: f32 = 0.05;
+
+    let camera_target = camera_res.camera.target;
+    let view_radius = camera_res.view_size().length() / 2.0;
+
     for (planet, kinematics) in query.iter() {
-        draw_planet(planet, kinematics, &textures);
+        let distance = (camera_target - kinematics.pos).length();
+        if distance > planet.radius + planet.terrain.max_height() + view_radius {
+            continue;
+        }
+
+        if view_radius < planet.radius * SURFACE_LOD_RATIO {
+            draw_surface_arc(planet, kinematics, camera_target, view_radius);
+        } else {
+            draw_planet(planet, kinematics, &textures);
+        }
     }
 }
@@ -0,0 +1,110 @@
+use bevy_ecs::prelude::*;
+use egui_macroquad::macroquad::prelude::Vec2;
+
+use crate::error::GameError;
+use crate::physics::offset::UniverseOffset;
+use crate::physics::{Kinematics, SimTime};
+use crate::rocket::{Altitude, Rocket};
+use crate::trajectory::Trajectory;
+
+/// The state of the rocket at the end of one physics tick
+#[derive(Copy, Clone)]
+pub struct FlightSample {
+    /// universal time, in simulated seconds
+    pub time: f64,
+    /// position in universe coordinates, unaffected by rebasing
+    pub pos: Vec2,
+    pub vel: Vec2,
+    pub altitude: f32,
+    pub throttle: f32,
+    pub angle: f32,
+    pub fuel: f32,
+    pub mass: f32,
+}
+
+const CSV_HEADER: &str = "time,pos_x,pos_y,vel_x,vel_y,altitude,throttle,angle,fuel,mass";
+
+/// most samples kept, an hour of ticks at the base rate
+const MAX_SAMPLES: usize = 216_000;
+
+/// The whole flight, for analysing in external tools. Every tick is kept until
+/// there are `MAX_SAMPLES`, then every other one is dropped and recording slows
+/// down to match, so long warped flights stay covered at a coarser resolution.
+#[derive(Default)]
+pub struct FlightRecorder {
+    pub samples: Vec<FlightSample>,
+    /// sim seconds between samples, zero to keep every tick
+    interval: f64,
+    /// result of the last export, shown in the UI
+    pub export_status: Option<String>,
+}
+
+impl FlightRecorder {
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::with_capacity((self.samples.len() + 1) * 80);
+        csv.push_str(CSV_HEADER);
+        csv.push('\n');
+
+        for sample in self.samples.iter() {
+            csv.push_str(&format!(
+                "{},{},{},{},{},{},{},{},{},{}\n",
+                sample.time,
+                sample.pos.x,
+                sample.pos.y,
+                sample.vel.x,
+                sample.vel.y,
+                sample.altitude,
+                sample.throttle,
+                sample.angle,
+                sample.fuel,
+                sample.mass,
+            ));
+        }
+
+        csv
+    }
+
+    /// Saves the recording as a CSV file, or downloads it in the browser, and returns
+    /// the file's name
+    pub fn export_csv(&self) -> Result<String, GameError> {
+        crate::files::save_text_file("flight", "csv", &self.to_csv())
+    }
+}
+
+pub fn record_flight_sys(
+    mut recorder: ResMut<FlightRecorder>,
+    query: Query<(&Rocket, &Kinematics, &Altitude), Without<Trajectory>>,
+    offset: Res<UniverseOffset>,
+    sim_time: Res<SimTime>,
+) {
+    let time = sim_time.universal;
+
+    if let Some(last) = recorder.samples.last() {
+        if time - last.time < recorder.interval {
+            return;
+        }
+    }
+
+    if recorder.samples.len() >= MAX_SAMPLES {
+        let mut i = 0;
+        recorder.samples.retain(|_| {
+            i += 1;
+            i % 2 == 1
+        });
+        let span = recorder.samples.last().unwrap().time - recorder.samples[0].time;
+        recorder.interval = span / recorder.samples.len() as f64;
+    }
+
+    for (rocket, kinematics, altitude) in query.iter() {
+        recorder.samples.push(FlightSample {
+            time,
+            pos: kinematics.pos + offset.offset,
+            vel: kinematics.vel,
+            altitude: altitude.height,
+            throttle: rocket.thrust,
+            angle: rocket.angle,
+            fuel: rocket.current_fuel_mass,
+            mass: rocket.current_fuel_mass + rocket.non_fuel_mass,
+        });
+    }
+}
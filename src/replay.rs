@@ -0,0 +1,500 @@
+use crate::error::GameError;
+use crate::physics::step::BASE_DT;
+use crate::physics::warp::WARP_FACTORS;
+use crate::rocket::Rocket;
+
+const HEADER: &str = "rocket_sim replay 5";
+
+/// What the player asked the rocket to do, gathered from input each frame
+///
+/// Held controls are rates, applied on every physics tick until the next frame
+/// updates them, so control response follows sim time rather than frame rate.
+/// One-shot actions are only applied on the next tick.
+#[derive(Copy, Clone, Default, PartialEq)]
+pub struct InputCommands {
+    /// 1 while rotating counter-clockwise, -1 while rotating clockwise
+    pub rotate: f32,
+    /// 1 while increasing the throttle, -1 while decreasing it
+    pub throttle: f32,
+    /// angle to turn towards while steering with the mouse
+    pub steer_towards: Option<f32>,
+    /// throttle to jump to
+    pub set_throttle: Option<f32>,
+    pub stage: bool,
+    pub toggle_sas: bool,
+}
+
+impl InputCommands {
+    pub fn is_empty(&self) -> bool {
+        *self == InputCommands::default()
+    }
+
+    /// Clears the one-shot actions once they've been applied, keeping held controls
+    pub fn clear_one_shots(&mut self) {
+        self.set_throttle = None;
+        self.stage = false;
+        self.toggle_sas = false;
+    }
+}
+
+fn optional_to_text(value: Option<f32>) -> String {
+    value.map_or_else(|| "-".to_string(), |value| value.to_string())
+}
+
+/// A frame spent in time warp, which happens between physics ticks
+#[derive(Copy, Clone)]
+pub struct WarpRecord {
+    /// index of the tick the warp came before
+    pub tick: usize,
+    /// index into `WARP_FACTORS`
+    pub level: usize,
+    /// sim seconds the frame asked to warp, which depends on its real length
+    pub duration: f32,
+}
+
+/// Everything needed to reproduce a flight: the rocket it started with, the input
+/// applied on each tick, and how the ticks and warps were timed
+#[derive(Clone)]
+pub struct Replay {
+    /// in firing order, as passed to `MainState::new`
+    pub stages: Vec<Rocket>,
+    /// tick index and input, only for ticks that had any
+    pub inputs: Vec<(usize, InputCommands)>,
+    /// tick index and length, only for ticks that weren't `BASE_DT` long
+    pub tick_lengths: Vec<(usize, f32)>,
+    /// several frames can warp before the same tick
+    pub warps: Vec<WarpRecord>,
+    /// how many ticks the flight lasted
+    pub ticks: usize,
+}
+
+impl Replay {
+    pub fn new(stages: Vec<Rocket>) -> Self {
+        Replay {
+            stages,
+            inputs: Vec::new(),
+            tick_lengths: Vec::new(),
+            warps: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    /// Floats are written with `Display`, which round-trips exactly
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+
+        for stage in self.stages.iter() {
+            let fields = rocket_fields(stage)
+                .iter()
+                .map(|field| field.to_string())
+                .collect::<Vec<_>>();
+            text.push_str(&format!("stage {}\n", fields.join(" ")));
+        }
+
+        for (tick, input) in self.inputs.iter() {
+            text.push_str(&format!(
+                "input {} {} {} {} {} {} {}\n",
+                tick,
+                input.rotate,
+                input.throttle,
+                optional_to_text(input.steer_towards),
+                optional_to_text(input.set_throttle),
+                input.stage as u8,
+                input.toggle_sas as u8
+            ));
+        }
+
+        for (tick, length) in self.tick_lengths.iter() {
+            text.push_str(&format!("length {} {}\n", tick, length));
+        }
+
+        for warp in self.warps.iter() {
+            text.push_str(&format!(
+                "warp {} {} {}\n",
+                warp.tick, warp.level, warp.duration
+            ));
+        }
+
+        text.push_str(&format!("ticks {}\n", self.ticks));
+        text
+    }
+
+    /// Playback walks through each list in order, so their ticks can't go backwards
+    pub fn from_text(text: &str) -> Result<Self, GameError> {
+        let invalid = |line: &str| GameError::InvalidReplay(format!("bad line: {}", line));
+
+        let mut lines = text.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(GameError::InvalidReplay("not a replay file".to_string()));
+        }
+
+        let mut replay = Replay::new(Vec::new());
+        for line in lines {
+            let mut words = line.split_whitespace();
+            let kind = words.next();
+            let words = words.collect::<Vec<_>>();
+            match kind {
+                Some("stage") => {
+                    let fields = words
+                        .iter()
+                        .map(|word| word.parse::<f32>())
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|_| invalid(line))?;
+                    replay
+                        .stages
+                        .push(rocket_from_fields(&fields).ok_or_else(|| invalid(line))?);
+                }
+                Some("input") if words.len() == 7 => {
+                    let parse = |word: &str| word.parse::<f32>().map_err(|_| invalid(line));
+                    let parse_optional = |word: &str| match word {
+                        "-" => Ok(None),
+                        word => parse(word).map(Some),
+                    };
+                    let tick = words[0].parse::<usize>().map_err(|_| invalid(line))?;
+                    if replay.inputs.last().is_some_and(|(last, _)| *last >= tick) {
+                        return Err(invalid(line));
+                    }
+                    replay.inputs.push((
+                        tick,
+                        InputCommands {
+                            rotate: parse(words[1])?,
+                            throttle: parse(words[2])?,
+                            steer_towards: parse_optional(words[3])?,
+                            set_throttle: parse_optional(words[4])?,
+                            stage: words[5] == "1",
+                            toggle_sas: words[6] == "1",
+                        },
+                    ));
+                }
+                Some("length") if words.len() == 2 => {
+                    let tick = words[0].parse::<usize>().map_err(|_| invalid(line))?;
+                    let length = words[1].parse::<f32>().map_err(|_| invalid(line))?;
+                    let out_of_order = replay
+                        .tick_lengths
+                        .last()
+                        .is_some_and(|(last, _)| *last >= tick);
+                    if out_of_order || !(length > 0.0 && length.is_finite()) {
+                        return Err(invalid(line));
+                    }
+                    replay.tick_lengths.push((tick, length));
+                }
+                Some("warp") if words.len() == 3 => {
+                    let parse = |word: &str| word.parse::<usize>().map_err(|_| invalid(line));
+                    let warp = WarpRecord {
+                        tick: parse(words[0])?,
+                        level: parse(words[1])?,
+                        duration: words[2].parse::<f32>().map_err(|_| invalid(line))?,
+                    };
+                    let out_of_order = replay
+                        .warps
+                        .last()
+                        .is_some_and(|last| last.tick > warp.tick);
+                    if out_of_order
+                        || warp.level >= WARP_FACTORS.len()
+                        || !(warp.duration > 0.0 && warp.duration.is_finite())
+                    {
+                        return Err(invalid(line));
+                    }
+                    replay.warps.push(warp);
+                }
+                Some("ticks") if words.len() == 1 => {
+                    replay.ticks = words[0].parse().map_err(|_| invalid(line))?;
+                }
+                None => {}
+                _ => return Err(invalid(line)),
+            }
+        }
+
+        if replay.stages.is_empty() {
+            return Err(GameError::InvalidReplay("no stages".to_string()));
+        }
+        Ok(replay)
+    }
+
+    pub fn save(&self) -> Result<String, GameError> {
+        crate::files::save_text_file("replay", "txt", &self.to_text())
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn load(path: &str) -> Result<Self, GameError> {
+        Replay::from_text(&std::fs::read_to_string(path)?)
+    }
+}
+
+fn rocket_fields(rocket: &Rocket) -> [f32; 12] {
+    [
+        rocket.fuel_capacity,
+        rocket.current_fuel_mass,
+        rocket.non_fuel_mass,
+        rocket.fuel_burn_rate,
+        rocket.fuel_thrust_factor,
+        rocket.angle,
+        rocket.thrust,
+        rocket.angular_vel,
+        rocket.drag_coefficient,
+        rocket.lift_coefficient,
+        rocket.cross_section,
+        rocket.stability,
+    ]
+}
+
+fn rocket_from_fields(fields: &[f32]) -> Option<Rocket> {
+    match *fields {
+        [fuel_capacity, current_fuel_mass, non_fuel_mass, fuel_burn_rate, fuel_thrust_factor, angle, thrust, angular_vel, drag_coefficient, lift_coefficient, cross_section, stability] => {
+            Some(Rocket {
+                fuel_capacity,
+                current_fuel_mass,
+                non_fuel_mass,
+                fuel_burn_rate,
+                fuel_thrust_factor,
+                angle,
+                thrust,
+                angular_vel,
+                drag_coefficient,
+                lift_coefficient,
+                cross_section,
+                stability,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Whether physics ticks take their input from the player or from a replay
+pub enum ReplayMode {
+    Recording,
+    /// `next_input` indexes `Replay::inputs`, `next_length` `Replay::tick_lengths`
+    /// and `next_warp` `Replay::warps`
+    Playback {
+        next_input: usize,
+        next_length: usize,
+        next_warp: usize,
+    },
+}
+
+/// The replay of the current flight, either being recorded or played back
+pub struct ReplayState {
+    pub replay: Replay,
+    pub mode: ReplayMode,
+    /// index of the next physics tick
+    pub tick: usize,
+    /// result of the last save, shown in the UI
+    pub save_status: Option<String>,
+}
+
+impl ReplayState {
+    pub fn recording(stages: Vec<Rocket>) -> Self {
+        ReplayState {
+            replay: Replay::new(stages),
+            mode: ReplayMode::Recording,
+            tick: 0,
+            save_status: None,
+        }
+    }
+
+    pub fn playback(replay: Replay) -> Self {
+        ReplayState {
+            replay,
+            mode: ReplayMode::Playback {
+                next_input: 0,
+                next_length: 0,
+                next_warp: 0,
+            },
+            tick: 0,
+            save_status: None,
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        matches!(self.mode, ReplayMode::Playback { .. })
+    }
+
+    /// Returns the input to apply this tick: `player_input` when recording, which is
+    /// saved if it isn't empty, or the replay's when playing back. Once the replay
+    /// runs out control goes back to the player, recording on from there.
+    pub fn next_input(&mut self, player_input: InputCommands) -> InputCommands {
+        let tick = self.tick;
+        self.tick += 1;
+
+        if let ReplayMode::Playback {
+            next_input,
+            next_length,
+            next_warp,
+        } = &mut self.mode
+        {
+            if tick < self.replay.ticks {
+                return match self.replay.inputs.get(*next_input) {
+                    Some((input_tick, input)) if *input_tick == tick => {
+                        *next_input += 1;
+                        *input
+                    }
+                    _ => InputCommands::default(),
+                };
+            }
+
+            // anything after the end of the recording is replaced by the player's input
+            let (next_input, next_length, next_warp) = (*next_input, *next_length, *next_warp);
+            self.replay.inputs.truncate(next_input);
+            self.replay.tick_lengths.truncate(next_length);
+            self.replay.warps.truncate(next_warp);
+            self.mode = ReplayMode::Recording;
+        }
+
+        if !player_input.is_empty() {
+            self.replay.inputs.push((tick, player_input));
+        }
+        self.replay.ticks = self.tick;
+        player_input
+    }
+
+    /// How long the next tick was when recorded, or `None` if the tick is up to the
+    /// player, either while recording or once the replay runs out
+    pub fn replayed_tick_length(&mut self) -> Option<f32> {
+        let tick = self.tick;
+        match &mut self.mode {
+            ReplayMode::Playback { next_length, .. } if tick < self.replay.ticks => {
+                match self.replay.tick_lengths.get(*next_length) {
+                    Some((length_tick, length)) if *length_tick == tick => {
+                        *next_length += 1;
+                        Some(*length)
+                    }
+                    _ => Some(BASE_DT),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Saves how long the tick that just ran was, if it wasn't `BASE_DT`. Ticks are
+    /// cut short to fit the time left in a frame, so their lengths depend on frame
+    /// timing and can't be worked out again on playback.
+    pub fn record_tick_length(&mut self, length: f32) {
+        if let ReplayMode::Recording = self.mode {
+            if length != BASE_DT {
+                self.replay.tick_lengths.push((self.tick - 1, length));
+            }
+        }
+    }
+
+    /// Whether the replay warps before the next tick
+    pub fn warp_pending(&self) -> bool {
+        match self.mode {
+            ReplayMode::Playback { next_warp, .. } => self
+                .replay
+                .warps
+                .get(next_warp)
+                .is_some_and(|warp| warp.tick == self.tick),
+            ReplayMode::Recording => false,
+        }
+    }
+
+    /// The warp level and duration to play the next frame at, if the replay warps
+    /// before the next tick
+    pub fn replayed_warp(&mut self) -> Option<(usize, f32)> {
+        if !self.warp_pending() {
+            return None;
+        }
+
+        match &mut self.mode {
+            ReplayMode::Playback { next_warp, .. } => {
+                let warp = self.replay.warps[*next_warp];
+                *next_warp += 1;
+                Some((warp.level, warp.duration))
+            }
+            ReplayMode::Recording => None,
+        }
+    }
+
+    /// Saves a frame warped at `level` for `duration`, warp doesn't go through the
+    /// input commands as it runs in place of ticks rather than during them
+    pub fn record_warp(&mut self, level: usize, duration: f32) {
+        if let ReplayMode::Recording = self.mode {
+            self.replay.warps.push(WarpRecord {
+                tick: self.tick,
+                level,
+                duration,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_replay() -> Replay {
+        let mut replay = Replay::new(vec![Rocket::default(), Rocket::default()]);
+        replay.inputs.push((
+            3,
+            InputCommands {
+                rotate: -1.0,
+                throttle: 0.5,
+                steer_towards: Some(0.1),
+                set_throttle: None,
+                stage: true,
+                toggle_sas: false,
+            },
+        ));
+        replay.tick_lengths.push((10, BASE_DT * 4.0));
+        replay.warps.push(WarpRecord {
+            tick: 12,
+            level: 2,
+            duration: 1.6,
+        });
+        replay.ticks = 20;
+        replay
+    }
+
+    #[test]
+    fn text_round_trips() {
+        let replay = sample_replay();
+        let text = replay.to_text();
+        let parsed = Replay::from_text(&text).unwrap();
+
+        assert_eq!(parsed.to_text(), text);
+        assert_eq!(parsed.stages.len(), 2);
+        assert_eq!(parsed.ticks, 20);
+        assert!(parsed.inputs[0].1 == replay.inputs[0].1);
+    }
+
+    #[test]
+    fn rejects_bad_header() {
+        let text = sample_replay()
+            .to_text()
+            .replacen(HEADER, "rocket_sim replay 0", 1);
+        assert!(Replay::from_text(&text).is_err());
+    }
+
+    #[test]
+    fn rejects_no_stages() {
+        let replay = Replay::new(Vec::new());
+        assert!(Replay::from_text(&replay.to_text()).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_warp() {
+        let text = sample_replay()
+            .to_text()
+            .replace("warp 12 2 1.6", "warp 12 2 0");
+        assert!(Replay::from_text(&text).is_err());
+    }
+
+    #[test]
+    fn rejects_out_of_order_ticks() {
+        let mut replay = sample_replay();
+        replay.inputs.push((2, InputCommands::default()));
+        assert!(Replay::from_text(&replay.to_text()).is_err());
+
+        let mut replay = sample_replay();
+        replay.tick_lengths.push((10, BASE_DT * 2.0));
+        assert!(Replay::from_text(&replay.to_text()).is_err());
+
+        let mut replay = sample_replay();
+        replay.warps.push(WarpRecord {
+            tick: 11,
+            level: 1,
+            duration: 0.5,
+        });
+        assert!(Replay::from_text(&replay.to_text()).is_err());
+    }
+}
@@ -1,12 +1,76 @@
+// `#[derive(Bundle)]` forgets each component after moving it into the world
+#![allow(clippy::forget_non_drop)]
+
 use bevy_ecs::prelude::*;
 use egui_macroquad::macroquad::prelude::*;
 
+use crate::actions::{Action, ActionMap};
+use crate::camera::{CameraMode, CameraRes};
+use crate::map::MapRes;
+use crate::physics::gforce::GForce;
+use crate::physics::heat::Heat;
+use crate::physics::step::{AdaptiveStep, BASE_DT};
 use crate::physics::Kinematics;
 use crate::planet::CelestialBody;
+use crate::replay::{InputCommands, ReplayState};
 use crate::trajectory::Trajectory;
+use crate::ui::PointerOverUi;
+
+use crate::graphs::Telemetry;
+
+/// length of the rocket, used for its moment of inertia
+pub const ROCKET_LENGTH: f32 = 0.2;
+/// width the rocket is drawn at in the flight view
+const ROCKET_SIZE: f32 = 0.1;
+/// smallest the rocket is drawn relative to the height of the view
+const MIN_ROCKET_SCREEN_PROPORTION: f32 = 0.02;
+
+/// How the flight ended, if it has
+#[derive(Copy, Clone, PartialEq)]
+pub enum FlightOutcome {
+    InFlight,
+    Crashed {
+        impact_speed: f32,
+    },
+    Overheated {
+        temperature: f32,
+    },
+    OverG {
+        g_force: f32,
+    },
+    /// out of fuel, either resting on the ground or in an orbit that never comes down
+    Stranded,
+}
+
+impl FlightOutcome {
+    pub fn is_over(&self) -> bool {
+        *self != FlightOutcome::InFlight
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            FlightOutcome::InFlight => "",
+            FlightOutcome::Crashed { .. } => "CRASHED",
+            FlightOutcome::Overheated { .. } => "OVERHEATED",
+            FlightOutcome::OverG { .. } => "BROKE APART",
+            FlightOutcome::Stranded => "STRANDED",
+        }
+    }
 
-use crate::graphs::SpeedGraph;
-pub struct RocketCrashed(pub bool);
+    pub fn description(&self) -> String {
+        match self {
+            FlightOutcome::InFlight => String::new(),
+            FlightOutcome::Crashed { impact_speed } => {
+                format!("Impact speed: {:.0}", impact_speed * 1000.0)
+            }
+            FlightOutcome::Overheated { temperature } => {
+                format!("Temperature: {:.0}", temperature)
+            }
+            FlightOutcome::OverG { g_force } => format!("Acceleration: {:.1} g", g_force),
+            FlightOutcome::Stranded => "Out of fuel".to_string(),
+        }
+    }
+}
 
 use crate::texture::{TextureName, Textures};
 
@@ -14,8 +78,12 @@ use crate::texture::{TextureName, Textures};
 pub struct RocketBundle {
     pub kinematics: Kinematics,
     pub rocket: Rocket,
-    pub speed_graph: SpeedGraph,
+    pub telemetry: Telemetry,
     pub altitude: Altitude,
+    pub stages: Stages,
+    pub heat: Heat,
+    pub g_force: GForce,
+    pub sas: Sas,
 }
 
 impl Default for RocketBundle {
@@ -26,8 +94,12 @@ impl Default for RocketBundle {
                 ..Kinematics::default()
             },
             rocket: Rocket::default(),
-            speed_graph: SpeedGraph(std::collections::VecDeque::new()),
+            telemetry: Telemetry::default(),
             altitude: Altitude::default(),
+            stages: Stages::default(),
+            heat: Heat::default(),
+            g_force: GForce::default(),
+            sas: Sas::default(),
         }
     }
 }
@@ -46,6 +118,15 @@ pub struct Rocket {
     pub angle: f32,
     // from 0 to 1
     pub thrust: f32,
+    pub angular_vel: f32,
+    pub drag_coefficient: f32,
+    pub lift_coefficient: f32,
+    /// frontal area used for drag and lift
+    pub cross_section: f32,
+    /// how far the center of pressure sits behind the center of mass, in world units,
+    /// negative values make the rocket aerodynamically unstable. Worked out from the
+    /// part stack in the editor, with full tanks.
+    pub stability: f32,
 }
 
 impl Default for Rocket {
@@ -58,6 +139,11 @@ impl Default for Rocket {
             fuel_thrust_factor: 500.0,
             angle: 0.0,
             thrust: 1.0,
+            angular_vel: 0.0,
+            drag_coefficient: 0.5,
+            lift_coefficient: 0.3,
+            cross_section: 0.01,
+            stability: 0.02,
         }
     }
 }
@@ -66,8 +152,54 @@ impl Rocket {
     pub fn total_mass(&self) -> f32 {
         self.current_fuel_mass + self.non_fuel_mass
     }
+
+    /// thrust force at full throttle. Engines are tuned per `BASE_DT` tick, so that
+    /// sets how much force their fuel gives, whatever the tick length.
+    pub fn max_thrust(&self) -> f32 {
+        self.fuel_burn_rate * BASE_DT * self.fuel_thrust_factor * crate::THRUST_MULTIPLIER
+    }
+
+    /// thrust per unit of fuel burned per second
+    pub fn exhaust_velocity(&self) -> f32 {
+        self.fuel_thrust_factor * BASE_DT * crate::THRUST_MULTIPLIER
+    }
+
+    /// delta-v left in the current fuel, from the rocket equation
+    pub fn delta_v(&self) -> f32 {
+        if self.fuel_burn_rate <= 0.0 {
+            return 0.0;
+        }
+
+        self.exhaust_velocity() * (self.total_mass() / self.non_fuel_mass).ln()
+    }
+
+    pub fn moment_of_inertia(&self) -> f32 {
+        self.total_mass() * ROCKET_LENGTH * ROCKET_LENGTH / 12.0
+    }
+
+    pub fn thrust_to_weight(&self, gravity: f32) -> f32 {
+        self.max_thrust() / (self.total_mass() * gravity)
+    }
 }
 
+/// Stability assist, which holds the rocket's attitude by cancelling its rotation
+#[derive(Default)]
+pub struct Sas {
+    pub enabled: bool,
+}
+
+/// how quickly SAS cancels rotation, per second
+const SAS_DAMPING: f32 = 5.0;
+/// how fast the rocket is turned by the controls, in radians per sim second
+const ROTATION_RATE: f32 = 0.75;
+/// how fast the throttle changes while its controls are held, per sim second
+const THROTTLE_RATE: f32 = 0.1;
+
+/// The stages above the active one, in firing order. Each stage's `non_fuel_mass`
+/// includes everything stacked above it.
+#[derive(Default, Clone)]
+pub struct Stages(pub Vec<Rocket>);
+
 pub struct Altitude {
     pub height: f32,
     pub closest_planet: Entity,
@@ -76,7 +208,7 @@ pub struct Altitude {
 impl Default for Altitude {
     fn default() -> Self {
         Altitude {
-            height: std::f32::MAX,
+            height: f32::MAX,
             closest_planet: Entity::new(0),
         }
     }
@@ -87,10 +219,10 @@ pub fn update_altitude_sys(
     planet_query: Query<(&Kinematics, &CelestialBody, Entity)>,
 ) {
     for (mut altitude, rocket_kinematics, _) in rocket_query.iter_mut() {
-        altitude.height = std::f32::MAX;
+        altitude.height = f32::MAX;
         for (planet_kinematics, planet, planet_entity) in planet_query.iter() {
-            let current_altitude =
-                (rocket_kinematics.pos - planet_kinematics.pos).length() - planet.radius;
+            let current_altitude = (rocket_kinematics.pos - planet_kinematics.pos).length()
+                - planet.surface_radius_at(planet_kinematics, rocket_kinematics.pos);
             if current_altitude < altitude.height {
                 altitude.height = current_altitude;
                 altitude.closest_planet = planet_entity;
@@ -120,77 +252,166 @@ pub fn draw_rocket(pos: &Vec2, angle: f32, thrust: bool, textures: &Textures, si
 }
 
 pub fn draw_rocket_sys(
-    query: Query<(&Rocket, &Kinematics), Without<Trajectory>>,
+    query: Query<(&Rocket, &Kinematics, &Heat), Without<Trajectory>>,
     textures: Res<Textures>,
+    camera_res: Res<CameraRes>,
 ) {
-    for (rocket, kinematics) in query.iter() {
+    // keep the rocket visible when zoomed out
+    let size = ROCKET_SIZE.max(camera_res.view_size().y * MIN_ROCKET_SCREEN_PROPORTION);
+
+    for (rocket, kinematics, heat) in query.iter() {
+        draw_reentry_glow(kinematics, heat, size);
         draw_rocket(
             &kinematics.pos,
             rocket.angle,
             rocket.current_fuel_mass > 0.0 && rocket.thrust > 0.0,
             &textures,
-            0.1,
+            size,
         );
     }
 }
 
+fn draw_reentry_glow(kinematics: &Kinematics, heat: &Heat, size: f32) {
+    let intensity = (heat.heating_rate / 200.0).min(1.0);
+    if intensity > 0.01 && kinematics.vel.length_squared() > 0.0 {
+        let front = kinematics.pos + kinematics.vel.normalize() * size * 0.6;
+        draw_circle(
+            front.x,
+            front.y,
+            size * (0.4 + 0.4 * intensity),
+            Color::new(1.0, 0.5, 0.1, 0.8 * intensity),
+        );
+        draw_circle(
+            front.x,
+            front.y,
+            size * (0.2 + 0.2 * intensity),
+            Color::new(1.0, 0.9, 0.6, 0.8 * intensity),
+        );
+    }
+}
+
+/// Gathers the player's input into `InputCommands` for the next physics ticks
 pub fn rocket_input_sys(
-    mut query: Query<(&mut Rocket, Option<&mut Trajectory>)>,
-    dt: Res<crate::physics::DT>,
+    mut commands: ResMut<InputCommands>,
+    replay: Res<ReplayState>,
+    action_map: Res<ActionMap>,
+    camera_res: Res<CameraRes>,
+    map_res: Res<MapRes>,
+    pointer_over_ui: Res<PointerOverUi>,
+    query: Query<&Kinematics, (With<Rocket>, Without<Trajectory>)>,
 ) {
-    if is_key_down(KeyCode::A) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.angle += 0.75 * dt.0;
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
-        }
+    if replay.is_playing() {
+        return;
     }
 
-    if is_key_down(KeyCode::D) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.angle -= 0.75 * dt.0;
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
+    // point to steer, except where the left button already drags the view
+    let steering_with_mouse = is_mouse_button_down(MouseButton::Left)
+        && !pointer_over_ui.0
+        && !map_res.shown
+        && camera_res.mode != CameraMode::FreePan;
+    commands.steer_towards = None;
+    if steering_with_mouse {
+        let cursor = camera_res
+            .camera
+            .screen_to_world(Vec2::from(mouse_position()));
+        for kinematics in query.iter() {
+            let to_cursor = cursor - kinematics.pos;
+            // the rocket points along (sin angle, cos angle)
+            commands.steer_towards = Some(to_cursor.x.atan2(to_cursor.y));
         }
     }
 
-    if is_key_down(KeyCode::Space) || is_key_down(KeyCode::C) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.thrust += 0.1 * dt.0;
-            rocket.thrust = rocket.thrust.min(1.0);
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
-        }
+    commands.rotate = 0.0;
+    if action_map.is_down(Action::RotateCCW) {
+        commands.rotate += 1.0;
+    }
+    if action_map.is_down(Action::RotateCW) {
+        commands.rotate -= 1.0;
     }
 
-    if is_key_down(KeyCode::Z) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.thrust -= 0.1 * dt.0;
-            rocket.thrust = rocket.thrust.max(0.0);
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
-        }
+    commands.throttle = 0.0;
+    if action_map.is_down(Action::ThrottleUp) {
+        commands.throttle += 1.0;
+    }
+    if action_map.is_down(Action::ThrottleDown) {
+        commands.throttle -= 1.0;
     }
 
-    if is_key_pressed(KeyCode::Q) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.thrust = 0.0;
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
+    if action_map.is_pressed(Action::ThrottleCut) {
+        commands.set_throttle = Some(0.0);
+    }
+
+    if action_map.is_pressed(Action::ThrottleMax) {
+        commands.set_throttle = Some(1.0);
+    }
+
+    if action_map.is_pressed(Action::Stage) {
+        commands.stage = true;
+    }
+
+    if action_map.is_pressed(Action::ToggleSas) {
+        commands.toggle_sas = !commands.toggle_sas;
+    }
+}
+
+/// Applies this tick's input, from the player or the replay being played back
+pub fn rocket_command_sys(
+    mut commands: ResMut<InputCommands>,
+    mut replay: ResMut<ReplayState>,
+    mut rocket_query: Query<(&mut Rocket, &mut Stages, &mut Sas, Option<&mut Trajectory>)>,
+    mut adaptive_step: ResMut<AdaptiveStep>,
+    mut dt: ResMut<crate::physics::DT>,
+) {
+    let input = replay.next_input(*commands);
+    commands.clear_one_shots();
+    if input.is_empty() {
+        return;
+    }
+
+    // controls act over a single tick, so a long one would overshoot them
+    dt.0 = dt.0.min(BASE_DT);
+    adaptive_step.reset();
+
+    // the trajectory's rocket is steered too, so it stays invalid while thrusting
+    for (mut rocket, mut stages, mut sas, trajectory) in rocket_query.iter_mut() {
+        if input.toggle_sas {
+            sas.enabled = !sas.enabled;
+        }
+        rocket.thrust = (rocket.thrust + input.throttle * THROTTLE_RATE * dt.0).clamp(0.0, 1.0);
+        if let Some(throttle) = input.set_throttle {
+            rocket.thrust = throttle;
+        }
+
+        rocket.angle += input.rotate * ROTATION_RATE * dt.0;
+        if let Some(target_angle) = input.steer_towards {
+            let turn = (target_angle - rocket.angle + std::f32::consts::PI)
+                .rem_euclid(std::f32::consts::TAU)
+                - std::f32::consts::PI;
+            let max_turn = ROTATION_RATE * dt.0;
+            rocket.angle += turn.clamp(-max_turn, max_turn);
+        }
+
+        // both rockets stage, so the prediction isn't left with the old stage's mass
+        if input.stage && !stages.0.is_empty() {
+            let next_stage = stages.0.remove(0);
+            *rocket = Rocket {
+                angle: rocket.angle,
+                angular_vel: rocket.angular_vel,
+                thrust: rocket.thrust,
+                ..next_stage
+            };
+        }
+
+        if let Some(mut trajectory) = trajectory {
+            trajectory.valid = false;
         }
     }
+}
 
-    if is_key_pressed(KeyCode::E) {
-        for (mut rocket, mut trajectory) in query.iter_mut() {
-            rocket.thrust = 1.0;
-            if let Some(t) = trajectory.as_deref_mut() {
-                t.valid = false;
-            }
+pub fn rocket_sas_sys(mut query: Query<(&mut Rocket, &Sas)>, dt: Res<crate::physics::DT>) {
+    for (mut rocket, sas) in query.iter_mut() {
+        if sas.enabled {
+            rocket.angular_vel *= (1.0 - SAS_DAMPING * dt.0).max(0.0);
         }
     }
 }
@@ -0,0 +1,75 @@
+/// Height of a body's surface above its radius, as a function of the angle around it
+pub enum Terrain {
+    /// layered value noise, each octave having twice the features and
+    /// `persistence` times the height of the last
+    Procedural {
+        seed: u32,
+        amplitude: f32,
+        features: usize,
+        octaves: usize,
+        persistence: f32,
+    },
+}
+
+impl Terrain {
+    pub fn max_height(&self) -> f32 {
+        match self {
+            Terrain::Procedural { amplitude, .. } => *amplitude,
+        }
+    }
+
+    /// `turn` is the angle around the body as a fraction of a full revolution
+    pub fn height_at(&self, turn: f32) -> f32 {
+        let turn = turn.rem_euclid(1.0);
+
+        match self {
+            Terrain::Procedural {
+                seed,
+                amplitude,
+                features,
+                octaves,
+                persistence,
+            } => {
+                let mut height = 0.0;
+                let mut max_height = 0.0;
+                let mut octave_amplitude = 1.0;
+                let mut octave_features = *features;
+
+                for octave in 0..*octaves {
+                    let octave_seed = seed.wrapping_add(octave as u32 * 0x9e37_79b9);
+                    height += octave_amplitude * value_noise(octave_seed, octave_features, turn);
+                    max_height += octave_amplitude;
+
+                    octave_amplitude *= persistence;
+                    octave_features *= 2;
+                }
+
+                height / max_height * amplitude
+            }
+        }
+    }
+}
+
+/// Smoothly interpolated noise in [0, 1] that wraps around after `features` lattice points
+fn value_noise(seed: u32, features: usize, turn: f32) -> f32 {
+    let x = turn * features as f32;
+    let i = x.floor() as usize % features;
+    let j = (i + 1) % features;
+    let t = x.fract();
+    let t = t * t * (3.0 - 2.0 * t);
+
+    let a = hash(seed, i as u32);
+    let b = hash(seed, j as u32);
+    a * (1.0 - t) + b * t
+}
+
+fn hash(seed: u32, x: u32) -> f32 {
+    let mut h = seed ^ x.wrapping_mul(0x27d4_eb2d);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x85eb_ca6b);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0xc2b2_ae35);
+    h ^= h >> 16;
+
+    h as f32 / u32::MAX as f32
+}
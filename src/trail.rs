@@ -0,0 +1,113 @@
+use std::collections::VecDeque;
+
+use bevy_ecs::prelude::*;
+use egui_macroquad::macroquad::prelude::*;
+
+use crate::camera::CameraRes;
+use crate::physics::offset::UniverseOffset;
+use crate::physics::Kinematics;
+use crate::rocket::RocketEntity;
+
+/// closest two recorded points can be, in world units
+const MIN_SPACING: f32 = 0.05;
+/// furthest apart two recorded points can be even when flying straight
+const MAX_SPACING: f32 = 200.0;
+/// how much the path has to turn, in radians, before a point is recorded
+const MIN_TURN: f32 = 0.02;
+const MAX_POINTS: usize = 5000;
+
+const TRAIL_COLOR: Color = Color::new(0.4, 0.8, 1.0, 0.6);
+
+/// The path the rocket has actually flown
+///
+/// Points are stored in universe coordinates, with the `UniverseOffset` added,
+/// so they stay where they were when the universe is rebased.
+pub struct FlightTrail {
+    pub points: VecDeque<Vec2>,
+}
+
+impl Default for FlightTrail {
+    fn default() -> Self {
+        FlightTrail {
+            points: VecDeque::with_capacity(MAX_POINTS),
+        }
+    }
+}
+
+impl FlightTrail {
+    /// Records `pos` only if the path has bent or gone far enough since the last point,
+    /// so straight stretches cost a single point
+    pub fn record(&mut self, pos: Vec2) {
+        let len = self.points.len();
+        let should_record = match (self.points.get(len.wrapping_sub(2)), self.points.back()) {
+            (_, None) => true,
+            (None, Some(last)) => (pos - *last).length() > MIN_SPACING,
+            (Some(second_last), Some(last)) => {
+                let last_dir = *last - *second_last;
+                let dir = pos - *last;
+                let spacing = dir.length();
+                let turn = last_dir.angle_between(dir).abs();
+
+                spacing > MAX_SPACING || (spacing > MIN_SPACING && turn > MIN_TURN)
+            }
+        };
+
+        if !should_record {
+            return;
+        }
+
+        if self.points.len() == MAX_POINTS {
+            self.points.pop_front();
+        }
+        self.points.push_back(pos);
+    }
+
+    /// Draws the trail up to the rocket at `rocket_pos`, fading out towards its oldest points
+    pub fn draw(&self, offset: Vec2, rocket_pos: Vec2, thickness: f32) {
+        let len = self.points.len();
+        let fst_iter = self.points.iter();
+        let snd_iter = self.points.iter().skip(1);
+
+        for (i, (fst, snd)) in fst_iter.zip(snd_iter).enumerate() {
+            let fst = *fst - offset;
+            let snd = *snd - offset;
+            let mut color = TRAIL_COLOR;
+            color.a *= (i + 1) as f32 / len as f32;
+            draw_line(fst.x, fst.y, snd.x, snd.y, thickness, color);
+        }
+
+        // the rocket has usually moved on since the last recorded point
+        if let Some(last) = self.points.back() {
+            let last = *last - offset;
+            draw_line(
+                last.x,
+                last.y,
+                rocket_pos.x,
+                rocket_pos.y,
+                thickness,
+                TRAIL_COLOR,
+            );
+        }
+    }
+}
+
+pub fn record_trail_sys(
+    mut trail: ResMut<FlightTrail>,
+    kinematics_query: Query<&Kinematics>,
+    rocket_entity: Res<RocketEntity>,
+    offset: Res<UniverseOffset>,
+) {
+    let kinematics = kinematics_query.get(rocket_entity.0).unwrap();
+    trail.record(kinematics.pos + offset.offset);
+}
+
+pub fn draw_trail_sys(
+    trail: Res<FlightTrail>,
+    camera_res: Res<CameraRes>,
+    kinematics_query: Query<&Kinematics>,
+    rocket_entity: Res<RocketEntity>,
+    offset: Res<UniverseOffset>,
+) {
+    let rocket_pos = kinematics_query.get(rocket_entity.0).unwrap().pos;
+    trail.draw(offset.offset, rocket_pos, camera_res.view_size().y / 500.0);
+}
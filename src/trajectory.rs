@@ -4,19 +4,21 @@ use bevy_ecs::prelude::*;
 use egui_macroquad::macroquad::prelude::*;
 
 use crate::main_state::MainState;
+use crate::physics::step::BASE_DT;
 use crate::physics::{Kinematics, Steps, DT};
 use crate::rocket::{Rocket, RocketEntity};
 
 #[derive(Copy, Clone)]
 pub struct TrajectorySyncClock {
-    pub tick: usize,
+    /// simulated time since the last point was dropped
+    pub time: f32,
     pub needed_ticks: usize,
 }
 
 impl Default for TrajectorySyncClock {
     fn default() -> Self {
         TrajectorySyncClock {
-            tick: 0,
+            time: 0.0,
             needed_ticks: 30,
         }
     }
@@ -45,12 +47,27 @@ pub fn _inspect_trajectory_pos_sys(query: Query<&Kinematics, With<Trajectory>>)
 }
 
 impl MainState {
-    pub fn add_trajectory_points(&mut self) {
-        let mut clock = self
+    /// Throws away the prediction and starts it again from where the rocket is now
+    pub fn reset_trajectory(&mut self) {
+        let rocket_entity = self.world.get_resource::<RocketEntity>().unwrap().0;
+        let main_rocket_kinematics = *self.world.get::<Kinematics>(rocket_entity).unwrap();
+        let main_rocket = *self.world.get::<Rocket>(rocket_entity).unwrap();
+
+        let mut trajectory_query = self
             .world
-            .get_resource::<TrajectorySyncClock>()
-            .unwrap()
-            .clone();
+            .query::<(&mut Trajectory, &mut Kinematics, &mut Rocket)>();
+        for (mut trajectory, mut kinematics, mut rocket) in
+            trajectory_query.iter_mut(&mut self.world)
+        {
+            trajectory.points.clear();
+            *kinematics = main_rocket_kinematics;
+            *rocket = main_rocket;
+        }
+    }
+
+    /// `time_run` is how much simulated time the rocket moved on by this frame
+    pub fn add_trajectory_points(&mut self, time_run: f32) {
+        let mut clock = *self.world.get_resource::<TrajectorySyncClock>().unwrap();
         self.world
             .insert_resource(DT(1.0 / 60.0 * clock.needed_ticks as f32));
 
@@ -72,22 +89,22 @@ impl MainState {
             let (mut trajectory, mut kinematics, mut rocket) =
                 trajectory_query.iter_mut(&mut self.world).next().unwrap();
 
-            if rocket.thrust > 0.0 {
-                trajectory.valid = false;
-            } else {
-                trajectory.valid = true;
-            }
+            trajectory.valid = rocket.thrust <= 0.0;
 
             if !trajectory.valid {
                 trajectory.points.clear();
                 *kinematics = main_rocket_kinematics;
                 *rocket = main_rocket;
-            } else if clock.tick * steps >= clock.needed_ticks {
-                clock.tick = 0;
-                trajectory.points.pop_front();
+            } else {
+                // drop the points the rocket has already flown past
+                let point_time = BASE_DT * clock.needed_ticks as f32;
+                clock.time += time_run;
+                while clock.time >= point_time {
+                    clock.time -= point_time;
+                    trajectory.points.pop_front();
+                }
             }
 
-            clock.tick += 1;
             trajectory.max_len - trajectory.points.len()
         };
         self.world.insert_resource(clock);
@@ -4,6 +4,7 @@ use crate::physics::Kinematics;
 use crate::rocket::Rocket;
 use crate::rocket::RocketEntity;
 use egui_macroquad::egui;
+use egui_macroquad::macroquad::prelude::{get_last_key_pressed, KeyCode};
 
 use bevy_ecs::entity::Entity;
 
@@ -12,9 +13,37 @@ use egui_macroquad::egui::Rect as EguiRect;
 // use egui_macroquad::egui::Vec2 as EguiVec;
 use egui_macroquad::macroquad::prelude::Rect;
 
-use crate::graphs::SpeedGraph;
+use crate::actions::{key_name, Action, ActionMap};
+use crate::camera::{CameraMode, CameraRes};
+use crate::graphs::{GraphSettings, Series, Telemetry, MAX_WINDOW};
+use crate::map::MapRes;
+use crate::physics::gforce::GForce;
+use crate::physics::heat::Heat;
 use crate::physics::offset::UniverseOffset;
-use crate::rocket::Altitude;
+use crate::physics::warp::{TimeWarp, WARP_FACTORS};
+use crate::physics::{SimControl, SimTime};
+use crate::planet::CelestialBody;
+use crate::recorder::FlightRecorder;
+use crate::replay::{InputCommands, ReplayState};
+use crate::rocket::{Altitude, Sas, Stages};
+
+/// Whether the mouse is over a window, so input there doesn't also reach the view
+/// or steer the rocket
+#[derive(Default)]
+pub struct PointerOverUi(pub bool);
+
+/// `seconds` as days, hours, minutes and seconds, leaving out the days until there are some
+fn format_duration(seconds: f64) -> String {
+    let total = seconds.max(0.0) as u64;
+    let (days, hours) = (total / 86_400, total / 3600 % 24);
+    let (minutes, seconds) = (total / 60 % 60, total % 60);
+
+    if days > 0 {
+        format!("{}d {:02}:{:02}:{:02}", days, hours, minutes, seconds)
+    } else {
+        format!("{:02}:{:02}:{:02}", hours, minutes, seconds)
+    }
+}
 
 fn _to_egui_rect(rect: &Rect) -> EguiRect {
     EguiRect::from_two_pos(
@@ -34,24 +63,45 @@ impl MainState {
             egui::Window::new("")
                 .id(egui::Id::new("Main"))
                 .show(egui_ctx, |ui| {
-                    let RocketEntity(rocket_entity) =
-                        self.world.get_resource::<RocketEntity>().unwrap();
-                    self.fuel_bar(rocket_entity, ui);
-                    self.throttle_bar(rocket_entity, ui);
-                    self.rocket_info(rocket_entity, ui);
+                    let rocket_entity = self.world.get_resource::<RocketEntity>().unwrap().0;
+                    self.fuel_bar(&rocket_entity, ui);
+                    self.throttle_slider(&rocket_entity, ui);
+                    self.action_buttons(&rocket_entity, ui);
+                    self.temperature_bar(&rocket_entity, ui);
+                    self.g_meter(&rocket_entity, ui);
+                    self.rocket_info(&rocket_entity, ui);
+                    self.delta_v_info(&rocket_entity, ui);
 
                     ui.add_space(5.0);
                     ui.separator();
                     ui.add_space(5.0);
 
                     self.time_speed_slider(ui);
+                    self.camera_settings(ui);
+                    ui.horizontal(|ui| {
+                        if ui.button("Back to Editor").clicked() {
+                            self.exit_to_editor = true;
+                        }
+                        if ui.button("Key Bindings").clicked() {
+                            self.keybindings_open = !self.keybindings_open;
+                        }
+                    });
 
                     ui.add_space(5.0);
                     ui.separator();
                     ui.add_space(5.0);
 
                     self.draw_graphs(ui);
+                    self.recorder_controls(ui);
+                    self.replay_controls(ui);
                 });
+
+            if self.keybindings_open {
+                self.keybindings_window(egui_ctx);
+            }
+
+            let pointer_over_ui = egui_ctx.wants_pointer_input() || egui_ctx.is_pointer_over_area();
+            self.world.insert_resource(PointerOverUi(pointer_over_ui));
         });
 
         egui_macroquad::draw();
@@ -59,6 +109,68 @@ impl MainState {
         Ok(())
     }
 
+    fn keybindings_window(&mut self, egui_ctx: &egui::CtxRef) {
+        if let Some(action) = self.rebinding {
+            match get_last_key_pressed() {
+                Some(KeyCode::Escape) => self.rebinding = None,
+                Some(key) if key_name(key).is_some() => {
+                    let mut action_map = self.world.get_resource_mut::<ActionMap>().unwrap();
+                    action_map.bind(action, key);
+                    self.rebinding = None;
+                }
+                _ => {}
+            }
+        }
+
+        let mut open = self.keybindings_open;
+        egui::Window::new("Key Bindings")
+            .open(&mut open)
+            .show(egui_ctx, |ui| {
+                egui::Grid::new("keybindings").show(ui, |ui| {
+                    for action in Action::ALL.iter() {
+                        ui.label(action.description());
+
+                        let keys = if self.rebinding == Some(*action) {
+                            "Press a key...".to_string()
+                        } else {
+                            let action_map = self.world.get_resource::<ActionMap>().unwrap();
+                            action_map
+                                .keys(*action)
+                                .iter()
+                                .filter_map(|key| key_name(*key))
+                                .collect::<Vec<_>>()
+                                .join(", ")
+                        };
+                        if ui.button(keys).clicked() {
+                            self.rebinding = Some(*action);
+                        }
+                        ui.end_row();
+                    }
+                });
+
+                ui.horizontal(|ui| {
+                    if ui.button("Reset to Defaults").clicked() {
+                        self.world.insert_resource(ActionMap::default());
+                        self.rebinding = None;
+                    }
+
+                    #[cfg(not(target_arch = "wasm32"))]
+                    if ui.button("Save").clicked() {
+                        let action_map = self.world.get_resource::<ActionMap>().unwrap();
+                        self.keybindings_status = Some(match action_map.save() {
+                            Ok(()) => "Saved".to_string(),
+                            Err(error) => format!("Save failed: {}", error),
+                        });
+                    }
+                });
+
+                if let Some(status) = &self.keybindings_status {
+                    ui.label(status);
+                }
+            });
+        self.keybindings_open = open;
+    }
+
     fn fuel_bar(&self, rocket_entity: &Entity, ui: &mut egui::Ui) {
         let rocket = self.world.get::<Rocket>(*rocket_entity).unwrap();
 
@@ -73,13 +185,83 @@ impl MainState {
         });
     }
 
-    fn throttle_bar(&self, rocket_entity: &Entity, ui: &mut egui::Ui) {
-        let rocket = self.world.get::<Rocket>(*rocket_entity).unwrap();
-        let thrust = rocket.thrust;
+    fn throttle_slider(&mut self, rocket_entity: &Entity, ui: &mut egui::Ui) {
+        let thrust = self.world.get::<Rocket>(*rocket_entity).unwrap().thrust;
+        let playing = self
+            .world
+            .get_resource::<ReplayState>()
+            .unwrap()
+            .is_playing();
 
+        let mut new_thrust = thrust;
         ui.horizontal(|ui| {
             ui.label("Throttle: ");
-            ui.add(egui::ProgressBar::new(thrust));
+            // the replay drives the throttle while it's playing
+            ui.set_enabled(!playing);
+            ui.add(egui::Slider::new(&mut new_thrust, 0.0..=1.0));
+        });
+
+        // goes through the input commands so it's recorded in the replay
+        if new_thrust != thrust {
+            let mut commands = self.world.get_resource_mut::<InputCommands>().unwrap();
+            commands.set_throttle = Some(new_thrust);
+        }
+    }
+
+    fn action_buttons(&mut self, rocket_entity: &Entity, ui: &mut egui::Ui) {
+        let sas_enabled = self.world.get::<Sas>(*rocket_entity).unwrap().enabled;
+        let playing = self
+            .world
+            .get_resource::<ReplayState>()
+            .unwrap()
+            .is_playing();
+
+        let mut stage = false;
+        let mut toggle_sas = false;
+        let mut toggle_map = false;
+        ui.horizontal(|ui| {
+            stage = ui
+                .add(egui::Button::new("Stage").enabled(!playing))
+                .clicked();
+            let sas_text = if sas_enabled { "SAS: On" } else { "SAS: Off" };
+            toggle_sas = ui
+                .add(egui::Button::new(sas_text).enabled(!playing))
+                .clicked();
+            toggle_map = ui.button("Map").clicked();
+        });
+
+        let mut commands = self.world.get_resource_mut::<InputCommands>().unwrap();
+        commands.stage |= stage;
+        commands.toggle_sas ^= toggle_sas;
+
+        if toggle_map {
+            let mut map_res = self.world.get_resource_mut::<MapRes>().unwrap();
+            map_res.shown = !map_res.shown;
+        }
+    }
+
+    fn temperature_bar(&self, rocket_entity: &Entity, ui: &mut egui::Ui) {
+        let heat = self.world.get::<Heat>(*rocket_entity).unwrap();
+        let temperature_proportion = heat.temperature / heat.max_temperature;
+
+        ui.horizontal(|ui| {
+            ui.label("Temperature:");
+            ui.add(egui::ProgressBar::new(temperature_proportion).text(format!(
+                "{:.0} / {:.0}",
+                heat.temperature, heat.max_temperature
+            )));
+        });
+    }
+
+    fn g_meter(&self, rocket_entity: &Entity, ui: &mut egui::Ui) {
+        let g_force = self.world.get::<GForce>(*rocket_entity).unwrap();
+
+        ui.horizontal(|ui| {
+            ui.label("G-Force:");
+            ui.add(
+                egui::ProgressBar::new(g_force.current / g_force.max)
+                    .text(format!("{:.1} g", g_force.current)),
+            );
         });
     }
 
@@ -101,35 +283,297 @@ impl MainState {
         ));
 
         ui.label(format!("Altitude: {:.2}", altitude.height * 1000.0));
+
+        if let Some(planet) = self.world.get::<CelestialBody>(altitude.closest_planet) {
+            let planet_kinematics = self
+                .world
+                .get::<Kinematics>(altitude.closest_planet)
+                .unwrap();
+            let orbital_speed = (kinematics.vel - planet_kinematics.vel).length();
+            let surface_speed = (kinematics.vel
+                - planet.air_velocity_at(planet_kinematics, kinematics.pos))
+            .length();
+
+            ui.label(format!("Orbital Speed: {:.2}", orbital_speed * 1000.0));
+            ui.label(format!("Surface Speed: {:.2}", surface_speed * 1000.0));
+        }
+    }
+
+    fn delta_v_info(&self, rocket_entity: &Entity, ui: &mut egui::Ui) {
+        let rocket = self.world.get::<Rocket>(*rocket_entity).unwrap();
+        let stages = self.world.get::<Stages>(*rocket_entity).unwrap();
+        let kinematics = self.world.get::<Kinematics>(*rocket_entity).unwrap();
+        let altitude = self.world.get::<Altitude>(*rocket_entity).unwrap();
+
+        let stage_delta_v = rocket.delta_v();
+        let total_delta_v =
+            stage_delta_v + stages.0.iter().map(|stage| stage.delta_v()).sum::<f32>();
+
+        ui.label(format!(
+            "Delta-v: {:.0} (stage), {:.0} (total)",
+            stage_delta_v * 1000.0,
+            total_delta_v * 1000.0
+        ));
+
+        if let Some(planet) = self.world.get::<CelestialBody>(altitude.closest_planet) {
+            let planet_pos = self
+                .world
+                .get::<Kinematics>(altitude.closest_planet)
+                .unwrap()
+                .pos;
+            let local_gravity = planet.gravity_at((kinematics.pos - planet_pos).length());
+            let throttle = if rocket.current_fuel_mass > 0.0 {
+                rocket.thrust
+            } else {
+                0.0
+            };
+
+            ui.label(format!(
+                "TWR: {:.2} (surface), {:.2} (local)",
+                throttle * rocket.thrust_to_weight(planet.surface_gravity()),
+                throttle * rocket.thrust_to_weight(local_gravity)
+            ));
+        }
     }
 
     fn time_speed_slider(&mut self, ui: &mut egui::Ui) {
+        let sim_time = self.world.get_resource::<SimTime>().unwrap();
+        match sim_time.launch {
+            Some(_) => ui.label(format!(
+                "Mission Time: T+ {}",
+                format_duration(sim_time.mission_elapsed())
+            )),
+            None => ui.label("Mission Time: not launched"),
+        };
+        ui.label(format!(
+            "Universal Time: {}",
+            format_duration(sim_time.universal)
+        ));
+
         ui.horizontal(|ui| {
+            let tick_length = self
+                .world
+                .get_resource::<crate::physics::step::AdaptiveStep>()
+                .unwrap()
+                .dt;
             let mut steps = self
                 .world
                 .get_resource_mut::<crate::physics::Steps>()
                 .unwrap();
 
-            ui.label("Sim Speed:");
+            ui.label("Physics Warp:");
             ui.add(egui::Slider::new(&mut steps.0, 1..=100));
+            ui.label(format!("Tick: {:.3} s", tick_length))
+                .on_hover_text("Ticks get longer in deep space, with the engines off");
+        });
+
+        // the replay drives the warp while it's playing
+        let playing = self
+            .world
+            .get_resource::<ReplayState>()
+            .unwrap()
+            .is_playing();
+        let mut time_warp = self.world.get_resource_mut::<TimeWarp>().unwrap();
+        ui.horizontal(|ui| {
+            ui.label("Time Warp:");
+            if ui
+                .add(egui::Button::new("<").enabled(!playing && time_warp.is_active()))
+                .clicked()
+            {
+                time_warp.level -= 1;
+            }
+            ui.label(format!("{}x", time_warp.factor()));
+            let can_speed_up = time_warp.level + 1 < WARP_FACTORS.len();
+            if ui
+                .add(egui::Button::new(">").enabled(!playing && can_speed_up))
+                .clicked()
+            {
+                time_warp.level += 1;
+                time_warp.status = None;
+            }
+        });
+        if let Some(status) = &time_warp.status {
+            ui.label(status);
+        }
+
+        let mut sim_control = self.world.get_resource_mut::<SimControl>().unwrap();
+        ui.horizontal(|ui| {
+            let pause_text = if sim_control.paused {
+                "Resume"
+            } else {
+                "Pause"
+            };
+            if ui.button(pause_text).clicked() {
+                sim_control.paused = !sim_control.paused;
+            }
+            if ui.button("Step").clicked() {
+                sim_control.step(1);
+            }
+
+            let step_size = sim_control.step_size;
+            if ui.button(format!("Step {}", step_size)).clicked() {
+                sim_control.step(step_size);
+            }
+            ui.add(egui::DragValue::new(&mut sim_control.step_size).clamp_range(1..=10_000));
+        });
+    }
+
+    fn camera_settings(&mut self, ui: &mut egui::Ui) {
+        let mut bodies = self.world.query::<(Entity, &CelestialBody)>();
+        let bodies = bodies
+            .iter(&self.world)
+            .map(|(entity, body)| (entity, body.name.clone()))
+            .collect::<Vec<_>>();
+
+        let mode = self.world.get_resource::<CameraRes>().unwrap().mode;
+        let mut new_mode = mode;
+        let selected_text = match mode {
+            CameraMode::Body(entity) => bodies
+                .iter()
+                .find(|(body, _)| *body == entity)
+                .map_or_else(|| mode.name().to_string(), |(_, name)| name.clone()),
+            _ => mode.name().to_string(),
+        };
+
+        ui.horizontal(|ui| {
+            egui::ComboBox::from_label("Camera")
+                .selected_text(selected_text)
+                .show_ui(ui, |ui| {
+                    for option in &[CameraMode::Chase, CameraMode::FreePan, CameraMode::Horizon] {
+                        ui.selectable_value(&mut new_mode, *option, option.name());
+                    }
+                    for (entity, name) in bodies.iter() {
+                        ui.selectable_value(&mut new_mode, CameraMode::Body(*entity), name);
+                    }
+                });
+
+            let mut camera_res = self.world.get_resource_mut::<CameraRes>().unwrap();
+            ui.checkbox(&mut camera_res.wobble, "Wobble");
+
+            let mut map_res = self.world.get_resource_mut::<MapRes>().unwrap();
+            ui.checkbox(&mut map_res.show_rings, "Map Rings");
         });
+
+        if new_mode != mode {
+            let offset = self.world.get_resource::<UniverseOffset>().unwrap().offset;
+            let mut camera_res = self.world.get_resource_mut::<CameraRes>().unwrap();
+            camera_res.set_mode(new_mode, offset);
+        }
+    }
+
+    fn recorder_controls(&mut self, ui: &mut egui::Ui) {
+        let mut recorder = self.world.get_resource_mut::<FlightRecorder>().unwrap();
+
+        ui.horizontal(|ui| {
+            ui.label(format!("Recorded: {} samples", recorder.samples.len()));
+            if ui.button("Export CSV").clicked() {
+                recorder.export_status = Some(match recorder.export_csv() {
+                    Ok(file_name) => format!("Saved {}", file_name),
+                    Err(error) => format!("Export failed: {}", error),
+                });
+            }
+        });
+
+        if let Some(status) = &recorder.export_status {
+            ui.label(status);
+        }
+    }
+
+    fn replay_controls(&mut self, ui: &mut egui::Ui) {
+        let mut replay_state = self.world.get_resource_mut::<ReplayState>().unwrap();
+        let mut replay_requested = None;
+
+        ui.horizontal(|ui| {
+            if replay_state.is_playing() {
+                ui.label(format!(
+                    "Replaying: tick {} / {}",
+                    replay_state.tick, replay_state.replay.ticks
+                ));
+            }
+            if ui.button("Save Replay").clicked() {
+                replay_state.save_status = Some(match replay_state.replay.save() {
+                    Ok(file_name) => format!("Saved {}", file_name),
+                    Err(error) => format!("Save failed: {}", error),
+                });
+            }
+            if ui.button("Watch Replay").clicked() {
+                replay_requested = Some(replay_state.replay.clone());
+            }
+        });
+
+        if let Some(status) = &replay_state.save_status {
+            ui.label(status);
+        }
+
+        if replay_requested.is_some() {
+            self.replay_requested = replay_requested;
+        }
     }
 
     fn draw_graphs(&mut self, ui: &mut egui::Ui) {
-        use egui::plot::{Line, Plot, Value, Values};
+        use egui::plot::{Legend, Line, Plot, Value, Values};
+
+        let mut settings = self.world.get_resource_mut::<GraphSettings>().unwrap();
+        ui.horizontal_wrapped(|ui| {
+            for series in Series::ALL.iter() {
+                let mut shown = settings.shown.contains(series);
+                if ui.checkbox(&mut shown, series.name()).changed() {
+                    if shown {
+                        settings.shown.push(*series);
+                    } else {
+                        settings.shown.retain(|other| other != series);
+                    }
+                }
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.label("History (s):");
+            ui.add(egui::Slider::new(&mut settings.window, 5.0..=MAX_WINDOW).logarithmic(true));
+        });
 
+        let settings = self.world.get_resource::<GraphSettings>().unwrap();
         let rocket_entity = self.world.get_resource::<RocketEntity>().unwrap().0;
-        let mut speed_graphs = self.world.query::<&SpeedGraph>();
-        let speed_graph = &speed_graphs.get(&self.world, rocket_entity).unwrap().0;
-        ui.label("Speed:");
-        ui.add_space(2.5);
-        ui.add(
-            Plot::new("velocity").line(Line::new(Values::from_values_iter(
-                speed_graph
-                    .iter()
-                    .enumerate()
-                    .map(|(i, y)| Value::new(i as f64, *y as f64)),
-            ))),
-        );
+        let telemetry = self.world.get::<Telemetry>(rocket_entity).unwrap();
+
+        let end_time = telemetry.samples.back().map_or(0.0, |sample| sample.time);
+        let start_time = end_time - settings.window as f64;
+        let window_samples = || {
+            telemetry
+                .samples
+                .iter()
+                .filter(move |sample| sample.time >= start_time)
+        };
+
+        // each unit gets its own plot, so the throttle isn't flattened by the altitude
+        let mut groups: Vec<Vec<Series>> = Vec::new();
+        for series in settings.shown.iter() {
+            match groups
+                .iter_mut()
+                .find(|group| group[0].unit() == series.unit())
+            {
+                Some(group) => group.push(*series),
+                None => groups.push(vec![*series]),
+            }
+        }
+
+        let height = (200.0 / groups.len() as f32).max(80.0);
+        for group in groups.iter() {
+            let mut plot = Plot::new(("telemetry", group[0].unit()))
+                .height(height)
+                .include_x(start_time.max(0.0))
+                .include_x(end_time)
+                .legend(Legend::default());
+            for series in group.iter() {
+                plot = plot.line(
+                    Line::new(Values::from_values_iter(window_samples().map(|sample| {
+                        Value::new(sample.time, sample.value(*series) as f64)
+                    })))
+                    .name(series.name()),
+                );
+            }
+
+            ui.add_space(2.5);
+            ui.add(plot);
+        }
     }
 }